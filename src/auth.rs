@@ -6,21 +6,66 @@
 // https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
 // modified, or distributed except according to those terms.
 
-use cert::Cert;
+use cert::{self, Cert, CertInfo};
 use inapi::ProjectConfig;
-use czmq::{ZCert, ZFrame, ZMsg, ZSock, SocketType};
+use czmq::{ZCert, ZMsg, ZSock, SocketType};
 use error::Result;
 use project;
 use read_conf;
 use std::{error, fmt};
 use std::path::Path;
-use zdaemon::ZMsgExtended;
+
+/// Milliseconds to wait for the auth server, unless the project sets
+/// "auth_timeout".
+const DEFAULT_TIMEOUT: i32 = 5000;
+
+/// Times to retry a request that is safe to repeat, unless the project
+/// sets "auth_retries".
+const DEFAULT_RETRIES: u32 = 3;
 
 pub struct Auth {
     sock: ZSock,
+    connector: Option<Connector>,
+    retries: u32,
+}
+
+/// Everything needed to open a new socket to the auth server.
+struct Connector {
+    endpoint: String,
+    /// User cert and the auth server's public key
+    curve: Option<(ZCert, String)>,
+    timeout: i32,
+}
+
+impl Connector {
+    fn connect(&self) -> Result<ZSock> {
+        let mut sock = ZSock::new(SocketType::REQ);
+        if let Some((ref user_cert, ref server_key)) = self.curve {
+            user_cert.apply(&mut sock);
+            sock.set_curve_serverkey(server_key);
+        }
+        // Don't hang on to unanswered requests when the socket is
+        // replaced.
+        sock.set_linger(0);
+        sock.set_sndtimeo(Some(self.timeout));
+        sock.set_rcvtimeo(Some(self.timeout));
+        try!(sock.connect(&self.endpoint));
+        Ok(sock)
+    }
 }
 
 impl Auth {
+    /// Talk to the auth server over an existing socket, so that tests
+    /// can play the server's side of a pipe.
+    #[cfg(test)]
+    pub fn with_sock(sock: ZSock) -> Auth {
+        Auth {
+            sock: sock,
+            connector: None,
+            retries: 0,
+        }
+    }
+
     pub fn new<P: AsRef<Path>>(project_path: P) -> Result<Auth> {
         let mut buf = project_path.as_ref().to_owned();
 
@@ -28,6 +73,15 @@ impl Auth {
         let config: ProjectConfig = read_conf(&buf)?;
         buf.pop();
 
+        let timeout = project::read_conf_key(&buf, "auth_timeout")?
+                              .and_then(|t| t.as_u64())
+                              .map(|t| t as i32)
+                              .unwrap_or(DEFAULT_TIMEOUT);
+        let retries = project::read_conf_key(&buf, "auth_retries")?
+                              .and_then(|r| r.as_u64())
+                              .map(|r| r as u32)
+                              .unwrap_or(DEFAULT_RETRIES);
+
         buf.push("auth.crt");
         let auth_cert = try!(ZCert::load(buf.to_str().unwrap()));
         buf.pop();
@@ -36,25 +90,51 @@ impl Auth {
         let user_cert = try!(ZCert::load(buf.to_str().unwrap()));
         buf.pop();
 
-        let mut sock = ZSock::new(SocketType::REQ);
-        user_cert.apply(&mut sock);
-        sock.set_curve_serverkey(auth_cert.public_txt());
-        sock.set_sndtimeo(Some(5000));
-        sock.set_rcvtimeo(Some(5000));
-        try!(sock.connect(&format!("tcp://{}:{}", config.auth_server, config.auth_api_port)));
+        let connector = Connector {
+            endpoint: format!("tcp://{}:{}", config.auth_server, config.auth_api_port),
+            curve: Some((user_cert, auth_cert.public_txt().into())),
+            timeout: timeout,
+        };
 
         Ok(Auth {
-            sock: sock,
+            sock: connector.connect()?,
+            connector: Some(connector),
+            retries: retries,
         })
     }
 
-    pub fn list(&mut self, cert_type: &str) -> Result<Vec<String>> {
-        let req = ZMsg::new();
-        req.addstr("cert::list")?;
-        req.addstr(cert_type)?;
-        req.send(&mut self.sock)?;
+    /// Send a request and wait for a reply with at least `min_frames`
+    /// frames. A REQ socket that misses its reply can't send again, so
+    /// on timeout the socket is replaced. Only `idempotent` requests
+    /// are retried, as the server may have acted on a request even
+    /// though we never saw the reply.
+    fn request(&mut self, req: ZMsg, min_frames: usize, idempotent: bool) -> Result<ZMsg> {
+        let attempts = if idempotent { self.retries + 1 } else { 1 };
+
+        for _ in 0..attempts {
+            // Sending consumes the message, so keep the original for
+            // retries.
+            if req.dup()?.send(&mut self.sock).is_ok() {
+                if let Ok(reply) = ZMsg::recv(&mut self.sock) {
+                    return if reply.size() < min_frames {
+                        Err(Error::HostResponse.into())
+                    } else {
+                        Ok(reply)
+                    };
+                }
+            }
 
-        let reply = ZMsg::expect_recv(&mut self.sock, 1, None, true)?;
+            if let Some(ref connector) = self.connector {
+                self.sock = connector.connect()?;
+            }
+        }
+
+        let endpoint = self.connector.as_ref().map(|c| c.endpoint.clone()).unwrap_or_default();
+        Err(Error::Unreachable(endpoint, attempts).into())
+    }
+
+    pub fn list(&mut self, cert_type: &str) -> Result<Vec<String>> {
+        let reply = self.request(new_request(&["cert::list", cert_type])?, 1, true)?;
 
         match reply.popstr().unwrap().or(Err(Error::HostResponse))?.as_ref() {
             "Ok" => {
@@ -76,14 +156,67 @@ impl Auth {
         }
     }
 
-    pub fn add(&mut self, cert_type: &str, name: &str) -> Result<Cert> {
-        let req = ZMsg::new();
-        try!(req.addstr("cert::create"));
-        try!(req.addstr(cert_type));
-        try!(req.addstr(name));
-        try!(req.send(&mut self.sock));
+    /// Fetch the public details of a cert, which must be of type
+    /// `cert_type`.
+    ///
+    /// The auth server only replies with the cert's public key, as
+    /// inapi expects. Metadata is read from a third frame if there is
+    /// one, which needs a server change to send it.
+    pub fn lookup(&mut self, cert_type: &str, name: &str) -> Result<CertInfo> {
+        let reply = self.request(new_request(&["cert::lookup", name])?, 2, true)?;
+
+        let info = match reply.popstr().unwrap().or(Err(Error::HostResponse))?.as_ref() {
+            "Ok" => {
+                if reply.size() != 1 && reply.size() != 2 {
+                    return Err(Error::HostResponse.into())
+                }
+
+                let pubkey = reply.popstr().unwrap().or(Err(Error::HostResponse))?;
+                let metadata = match reply.popbytes()? {
+                    Some(meta) => {
+                        // ZCert is the only metadata decoder we have, so
+                        // borrow a throwaway one.
+                        let zcert = ZCert::new()?;
+                        try!(zcert.decode_meta(&meta));
+                        Some(cert::metadata(&zcert))
+                    },
+                    None => None,
+                };
+
+                CertInfo {
+                    name: name.into(),
+                    public_key: pubkey,
+                    metadata: metadata,
+                }
+            },
+            "Err" => {
+                let e = reply.popstr().unwrap().or(Err(Error::HostResponse))?;
+                return Err(Error::HostError(e).into());
+            },
+            _ => return Err(Error::HostResponse.into()),
+        };
+
+        // Lookups don't say what type a cert is, but lists do
+        if self.list(cert_type)?.iter().any(|n| n == name) {
+            Ok(info)
+        } else {
+            Err(Error::WrongType(name.into(), cert_type.into()).into())
+        }
+    }
+
+    /// Create a cert, attaching any extra `meta` to it. The server adds
+    /// its own metadata, such as the cert's type and creation time.
+    pub fn add(&mut self, cert_type: &str, name: &str, meta: &[(String, String)]) -> Result<Cert> {
+        let req = new_request(&["cert::create", cert_type, name])?;
+        if !meta.is_empty() {
+            let zcert = ZCert::new()?;
+            for &(ref key, ref value) in meta {
+                zcert.set_meta(key, value);
+            }
+            req.addbytes(&zcert.encode_meta())?;
+        }
 
-        let reply = ZMsg::expect_recv(&mut self.sock, 2, None, true)?;
+        let reply = self.request(req, 2, false)?;
 
         match reply.popstr().unwrap().or(Err(Error::HostResponse))?.as_ref() {
             "Ok" => {
@@ -108,17 +241,14 @@ impl Auth {
     }
 
     pub fn delete(&mut self, name: &str) -> Result<()> {
-        let req = ZMsg::new();
-        try!(req.addstr("cert::delete"));
-        try!(req.addstr(name));
-        try!(req.send(&mut self.sock));
-
-        let result = try!(ZFrame::recv(&mut self.sock));
+        let reply = self.request(new_request(&["cert::delete", name])?, 1, false)?;
 
-        match try!(try!(result.data()).or(Err(Error::HostResponse))).as_ref() {
+        match reply.popstr().unwrap().or(Err(Error::HostResponse))?.as_ref() {
             "Ok" => Ok(()),
             "Err" => {
-                let e = try!(try!(self.sock.recv_str()).or(Err(Error::HostResponse)));
+                let e = reply.popstr()
+                             .ok_or(Error::HostResponse)?
+                             .or(Err(Error::HostResponse))?;
                 Err(Error::HostError(e).into())
             },
             _ => Err(Error::HostResponse.into()),
@@ -126,17 +256,29 @@ impl Auth {
     }
 }
 
+fn new_request(frames: &[&str]) -> Result<ZMsg> {
+    let req = ZMsg::new();
+    for frame in frames {
+        req.addstr(frame)?;
+    }
+    Ok(req)
+}
+
 #[derive(Debug)]
 pub enum Error {
     HostError(String),
     HostResponse,
+    Unreachable(String, u32),
+    WrongType(String, String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::HostError(ref e) => write!(f, "Auth server encountered an error: {}", e),
-            Error::HostResponse => write!(f, "Invalid response from host"),
+            Error::HostResponse => write!(f, "Invalid response from auth server"),
+            Error::Unreachable(ref e, attempts) => write!(f, "Auth server at {} did not respond after {} attempt(s)", e, attempts),
+            Error::WrongType(ref n, ref t) => write!(f, "{} is not a {} certificate", n, t),
         }
     }
 }
@@ -145,7 +287,9 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::HostError(ref e) => e,
-            Error::HostResponse => "Invalid response from host",
+            Error::HostResponse => "Invalid response from auth server",
+            Error::Unreachable(..) => "Auth server did not respond",
+            Error::WrongType(..) => "Certificate is of the wrong type",
         }
     }
 }
@@ -153,7 +297,7 @@ impl error::Error for Error {
 #[cfg(test)]
 mod tests {
     use inapi::ProjectConfig;
-    use czmq::{ZCert, ZMsg, ZSys};
+    use czmq::{SocketType, ZCert, ZMsg, ZSock, ZSys};
     use language::Language;
     use project;
     use write_conf;
@@ -213,6 +357,8 @@ mod tests {
 
         let mut auth = Auth {
             sock: client,
+            connector: None,
+            retries: 0,
         };
 
         let mut list = auth.list("host").unwrap();
@@ -236,6 +382,10 @@ mod tests {
             assert_eq!(&req.popstr().unwrap().unwrap(), "host");
             assert_eq!(&req.popstr().unwrap().unwrap(), "foobar");
 
+            let meta = ZCert::new().unwrap();
+            meta.decode_meta(&req.popbytes().unwrap().unwrap()).unwrap();
+            assert_eq!(meta.meta("role").unwrap().unwrap(), "web");
+
             let rep = ZMsg::new();
             rep.addstr("Ok").unwrap();
             rep.addstr("0000000000000000000000000000000000000000").unwrap();
@@ -253,9 +403,85 @@ mod tests {
 
         let mut auth = Auth {
             sock: client,
+            connector: None,
+            retries: 0,
         };
-        assert!(auth.add("host", "foobar").is_ok());
-        assert!(auth.add("host", "foobar").is_err());
+        assert!(auth.add("host", "foobar", &[("role".into(), "web".into())]).is_ok());
+        assert!(auth.add("host", "foobar", &[]).is_err());
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_lookup() {
+        ZSys::init();
+
+        let (client, mut server) = ZSys::create_pipe().unwrap();
+
+        let handle = spawn(move|| {
+            let cert = ZCert::new().unwrap();
+            cert.set_meta("type", "host");
+
+            let list = |server: &mut ZSock, names: &[&str]| {
+                let req = ZMsg::recv(server).unwrap();
+                assert_eq!(&req.popstr().unwrap().unwrap(), "cert::list");
+                let rep = ZMsg::new();
+                rep.addstr("Ok").unwrap();
+                for name in names {
+                    rep.addstr(name).unwrap();
+                }
+                rep.send(server).unwrap();
+            };
+
+            // The server as it is now, sending only the public key
+            let req = ZMsg::recv(&mut server).unwrap();
+            assert_eq!(&req.popstr().unwrap().unwrap(), "cert::lookup");
+            assert_eq!(&req.popstr().unwrap().unwrap(), "foobar");
+
+            let rep = ZMsg::new();
+            rep.addstr("Ok").unwrap();
+            rep.addstr(cert.public_txt()).unwrap();
+            rep.send(&mut server).unwrap();
+            list(&mut server, &["foobar"]);
+
+            // A server that sends metadata too
+            ZMsg::recv(&mut server).unwrap();
+
+            let rep = ZMsg::new();
+            rep.addstr("Ok").unwrap();
+            rep.addstr(cert.public_txt()).unwrap();
+            rep.addbytes(&cert.encode_meta()).unwrap();
+            rep.send(&mut server).unwrap();
+            list(&mut server, &["foobar"]);
+
+            ZMsg::recv(&mut server).unwrap();
+
+            let rep = ZMsg::new();
+            rep.addstr("Ok").unwrap();
+            rep.addstr(cert.public_txt()).unwrap();
+            rep.send(&mut server).unwrap();
+            list(&mut server, &[]);
+
+            ZMsg::recv(&mut server).unwrap();
+
+            let rep = ZMsg::new();
+            rep.addstr("Err").unwrap();
+            rep.addstr("Not found").unwrap();
+            rep.send(&mut server).unwrap();
+        });
+
+        let mut auth = Auth {
+            sock: client,
+            connector: None,
+            retries: 0,
+        };
+        let info = auth.lookup("host", "foobar").unwrap();
+        assert_eq!(info.name, "foobar");
+        assert_eq!(info.metadata, None);
+        let info = auth.lookup("host", "foobar").unwrap();
+        assert_eq!(info.metadata, Some(vec![("type".to_owned(), "host".to_owned())]));
+        assert!(auth.lookup("user", "foobar").is_err());
+        assert!(auth.lookup("host", "foobar").is_err());
 
         handle.join().unwrap();
     }
@@ -282,10 +508,72 @@ mod tests {
 
         let mut auth = Auth {
             sock: client,
+            connector: None,
+            retries: 0,
         };
         assert!(auth.delete("foobar").is_ok());
         assert!(auth.delete("foobar").is_err());
 
         handle.join().unwrap();
     }
+
+    #[test]
+    fn test_retry() {
+        ZSys::init();
+
+        let mut server = ZSock::new(SocketType::ROUTER);
+        server.bind("inproc://auth_test_retry").unwrap();
+
+        let handle = spawn(move|| {
+            // Drop the first request so that the client times out
+            ZMsg::recv(&mut server).unwrap();
+
+            let req = ZMsg::recv(&mut server).unwrap();
+            let identity = req.pop().unwrap();
+            let delimiter = req.pop().unwrap();
+            assert_eq!(&req.popstr().unwrap().unwrap(), "cert::list");
+
+            let rep = ZMsg::new();
+            rep.append(identity).unwrap();
+            rep.append(delimiter).unwrap();
+            rep.addstr("Ok").unwrap();
+            rep.addstr("web1").unwrap();
+            rep.send(&mut server).unwrap();
+        });
+
+        let connector = Connector {
+            endpoint: "inproc://auth_test_retry".into(),
+            curve: None,
+            timeout: 100,
+        };
+        let mut auth = Auth {
+            sock: connector.connect().unwrap(),
+            connector: Some(connector),
+            retries: 1,
+        };
+        assert_eq!(auth.list("host").unwrap(), vec!["web1"]);
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_unreachable() {
+        ZSys::init();
+
+        let connector = Connector {
+            endpoint: "inproc://auth_test_unreachable".into(),
+            curve: None,
+            timeout: 50,
+        };
+        let mut auth = Auth {
+            sock: connector.connect().unwrap(),
+            connector: Some(connector),
+            retries: 2,
+        };
+
+        match auth.list("host") {
+            Err(::error::Error::Auth(Error::Unreachable(_, attempts))) => assert_eq!(attempts, 3),
+            _ => panic!("Expected auth server to be unreachable"),
+        }
+    }
 }
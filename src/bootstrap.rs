@@ -7,16 +7,29 @@
 // modified, or distributed except according to those terms.
 
 use auth::Auth;
+use cert::Cert;
+use czmq::ZCert;
 use error::{Error, Result};
-use inapi::ProjectConfig;
+use hostdata::{self, Facts};
+use hostkey::HostKeyCheck;
+use inapi::{self, Host, ProjectConfig};
+use preflight::{self, PreflightError, Report};
+use privilege::{shell_quote, BecomeMethod, Escalation, PrivilegeError};
 use project;
+use serde_json::Value;
 use read_conf;
-use ssh2::Session;
-use std::env;
+use ssh::{Credentials, JumpHost, Timeouts};
+use std::{env, fs, mem, thread};
+use std::collections::HashMap;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::net::TcpStream;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+use template::Template;
+use transport::{SshTransport, Transport};
 
 const BOOTSTRAP_SOURCE: &'static str = "#!/bin/sh
 set -u
@@ -25,18 +38,20 @@ main() {
     # Run any user-defined preinstall scripts
     {{PREINSTALL}}
 
-    need_cmd curl
-
     local _tmpdir=\"$(mktemp -d 2>/dev/null || mktemp -d -t intecture)\"
     cd $_tmpdir
 
     # Install agent
-    curl -sSf https://get.intecture.io | sh -s -- -y -d $_tmpdir agent || exit 1
+    {{INSTALLAGENT}}
 
-    # Create agent cert
+    # Create agent cert, which holds the host's secret key, so that
+    # only we can read it
+    local _umask=\"$(umask)\"
+    umask 077
     cat << \"EOF\" > agent.crt
 {{AGENTCERT}}
 EOF
+    umask $_umask
 
     # Create auth cert
     cat << \"EOF\" > auth.crt
@@ -44,20 +59,23 @@ EOF
 EOF
 
     {{SUDO}} $_tmpdir/agent/installer.sh install_certs agent.crt auth.crt
+    shred -u agent.crt 2>/dev/null || rm -P agent.crt 2>/dev/null || rm -f agent.crt
+    rm -f auth.crt
     {{SUDO}} $_tmpdir/agent/installer.sh amend_conf auth_server \"{{AUTHHOST}}\"
     {{SUDO}} $_tmpdir/agent/installer.sh amend_conf auth_update_port {{AUTHPORT}}
     {{SUDO}} $_tmpdir/agent/installer.sh start_daemon
 
     # Check that inagent is up and running
     sleep 1
-    local _pid=$(pgrep -x inagent)
-    if [ ! -n $_pid ]; then
+    if ! pgrep -x inagent > /dev/null; then
         echo \"Failed to start inagent daemon\" >&2
         exit 1
     fi
 
     # Run any user-defined postinstall scripts
     {{POSTINSTALL}}
+
+    cd / && {{SUDO}} rm -rf $_tmpdir
 }
 
 need_cmd() {
@@ -70,93 +88,967 @@ need_cmd() {
 main || exit 1
 ";
 
+const INSTALL_ONLINE: &'static str = "need_cmd curl
+    curl -sSf https://get.intecture.io | sh -s -- -y -d $_tmpdir agent{{AGENTVERSION}} || exit 1";
+
+// The agent bundle is a package tarball, which ships with its own
+// installer.sh.
+const INSTALL_OFFLINE: &'static str = "mkdir agent
+    tar -xf \"{{AGENTBUNDLE}}\" -C agent --strip-components 1 || exit 1
+    rm -f \"{{AGENTBUNDLE}}\"
+    (cd agent && {{SUDO}} ./installer.sh install) || exit 1";
+
+// Swaps in a new host cert and restarts the agent. The agent is
+// already installed, so we only touch the cert named in its config.
+const REKEY_SOURCE: &'static str = "#!/bin/sh
+set -u
+
+main() {
+    find_conf || exit 1
+
+    umask 077
+    local _tmpdir=\"$(mktemp -d 2>/dev/null || mktemp -d -t intecture)\"
+    cd $_tmpdir
+
+    # Create agent cert
+    cat << \"EOF\" > agent.crt
+{{AGENTCERT}}
+EOF
+
+    {{SUDO}} cp agent.crt \"$_agentcert\"
+    local _status=$?
+    shred_file agent.crt
+    cd / && rmdir $_tmpdir
+    if [ $_status -ne 0 ]; then
+        echo \"Failed to install new agent cert\" >&2
+        exit 1
+    fi
+
+    # Restart agent with its new cert
+    restart_agent
+
+    # Check that inagent is up and running
+    sleep 1
+    if ! pgrep -x inagent > /dev/null; then
+        echo \"Failed to restart inagent daemon\" >&2
+        exit 1
+    fi
+}
+
+{{AGENTHELPERS}}
+
+main || exit 1
+";
+
+// Replaces the agent binary, keeping the host's certs and config.
+const UPGRADE_SOURCE: &'static str = "#!/bin/sh
+set -u
+
+main() {
+    find_conf || exit 1
+    local _bin=\"$(command -v inagent 2>/dev/null || echo /usr/local/bin/inagent)\"
+
+    umask 077
+    local _tmpdir=\"$(mktemp -d 2>/dev/null || mktemp -d -t intecture)\"
+    cd $_tmpdir
+
+    # Keep the binary, certs and config, which the installer may
+    # overwrite
+    {{SUDO}} cp -p \"$_bin\" inagent.old || exit 1
+    if ! {{SUDO}} cp -Rp \"$_confdir\" conf; then
+        shred_tree conf
+        exit 1
+    fi
+
+    # Stop agent
+    {{SUDO}} pkill -x inagent
+    sleep 1
+
+    # Install new agent
+    (
+    {{INSTALLAGENT}}
+    )
+    if [ $? -ne 0 ]; then
+        echo \"Failed to install new agent; restarting the old one\" >&2
+        {{SUDO}} cp -p inagent.old \"$_bin\"
+        {{SUDO}} cp -Rp conf/. \"$_confdir/\"
+        shred_tree conf
+        restart_agent
+        exit 1
+    fi
+
+    {{SUDO}} cp -Rp conf/. \"$_confdir/\"
+    local _status=$?
+    shred_tree conf
+    [ $_status -eq 0 ] || exit 1
+    {{SUDO}} $_tmpdir/agent/installer.sh start_daemon
+
+    # Check that inagent is up and running
+    sleep 1
+    if ! pgrep -x inagent > /dev/null; then
+        echo \"Failed to restart inagent daemon\" >&2
+        exit 1
+    fi
+
+    cd / && {{SUDO}} rm -rf $_tmpdir
+}
+
+need_cmd() {
+    if ! command -v \"$1\" > /dev/null 2>&1; then
+        echo \"need '$1' (command not found)\" >&2
+        exit 1
+    fi
+}
+
+{{AGENTHELPERS}}
+
+main || exit 1
+";
+
+// Shell functions for scripts that manage an installed agent.
+const AGENT_HELPERS_SOURCE: &'static str = "# Sets _conf and _confdir to the agent's config file and its dir, and
+# _agentcert and _authcert to the certs it names.
+find_conf() {
+    for _conf in /usr/local/etc/intecture/agent.json /etc/intecture/agent.json; do
+        {{SUDO}} test -f \"$_conf\" || continue
+        _confdir=\"$(dirname \"$_conf\")\"
+        _agentcert=\"$({{SUDO}} cat \"$_conf\" | conf_value agent_cert)\"
+        _authcert=\"$({{SUDO}} cat \"$_conf\" | conf_value auth_cert)\"
+        if [ -z \"$_agentcert\" ] || [ -z \"$_authcert\" ]; then
+            echo \"Could not read cert paths from $_conf\" >&2
+            return 1
+        fi
+        return 0
+    done
+
+    echo \"Could not find agent config\" >&2
+    return 1
+}
+
+# Prints the string value of key $1 from the JSON on stdin
+conf_value() {
+    sed -n \"s/.*\\\"$1\\\"[[:space:]]*:[[:space:]]*\\\"\\([^\\\"]*\\)\\\".*/\\1/p\" | head -n 1
+}
+
+# Overwrites and removes files that may hold a secret key
+shred_file() {
+    shred -u \"$@\" 2>/dev/null || rm -P \"$@\" 2>/dev/null || rm -f \"$@\"
+}
+
+# Overwrites and removes, as root, every file under dir $1, then the dir
+shred_tree() {
+    {{SUDO}} sh -c 'find \"$1\" -type f | while read -r _f; do shred -u \"$_f\" 2>/dev/null || rm -P \"$_f\" 2>/dev/null || rm -f \"$_f\"; done; rm -rf \"$1\"' sh \"$1\"
+}
+
+restart_agent() {
+    {{SUDO}} systemctl restart inagent > /dev/null 2>&1 || {{SUDO}} service inagent restart > /dev/null 2>&1
+}";
+
+const DECOMMISSION_SOURCE: &'static str = "#!/bin/sh
+set -u
+
+main() {
+    find_conf || exit 1
+    local _bin=\"$(command -v inagent 2>/dev/null || echo /usr/local/bin/inagent)\"
+
+    # Stop agent
+    {{SUDO}} systemctl stop inagent > /dev/null 2>&1 || {{SUDO}} service inagent stop > /dev/null 2>&1
+    {{SUDO}} systemctl disable inagent > /dev/null 2>&1
+    {{SUDO}} pkill -x inagent
+
+    sleep 1
+    if pgrep -x inagent > /dev/null; then
+        echo \"Failed to stop inagent daemon\" >&2
+        exit 1
+    fi
+
+    # Uninstall agent
+    {{SUDO}} rm -f \"$_bin\" \\
+        /lib/systemd/system/inagent.service \\
+        /etc/systemd/system/inagent.service \\
+        /etc/init.d/inagent \\
+        /usr/local/etc/rc.d/inagent
+
+    # Securely remove the agent's config and certs, which hold the
+    # host's secret key. As the config dir may only be readable by
+    # root, look inside it as root. Other components may keep their
+    # files there too, so only remove the dir if it's now empty.
+    {{SUDO}} sh -c 'for _file in \"$@\"; do [ -f \"$_file\" ] || continue; shred -u \"$_file\" 2>/dev/null || rm -P \"$_file\" 2>/dev/null || rm -f \"$_file\"; done' sh \"$_conf\" \"$_agentcert\" \"$_authcert\"
+    {{SUDO}} rmdir \"$_confdir\" 2>/dev/null || true
+}
+
+{{AGENTHELPERS}}
+
+main || exit 1
+";
+
+/// Options controlling what the bootstrap script installs.
+#[derive(Clone, Debug, Default)]
+pub struct InstallOptions {
+    /// Shell snippet to run before installing the agent.
+    pub preinstall: Option<String>,
+    /// Local script to upload and run before installing the agent.
+    pub preinstall_file: Option<String>,
+    /// Shell snippet to run after the agent has started.
+    pub postinstall: Option<String>,
+    /// Local script to upload and run after the agent has started.
+    pub postinstall_file: Option<String>,
+    /// Local agent package to upload, instead of downloading the agent
+    /// from get.intecture.io.
+    pub agent_bundle: Option<String>,
+    /// Agent version to download from get.intecture.io, instead of the
+    /// latest release. See `agent_version`.
+    pub agent_version: Option<String>,
+    /// Use the host cert saved in "<hostname>.crt" rather than
+    /// creating a new one.
+    pub reuse_cert: bool,
+    /// Extra variables for the bootstrap template.
+    pub vars: Vec<(String, String)>,
+    /// Local files to copy to the host before installing, as
+    /// (local, remote) paths.
+    pub uploads: Vec<(String, String)>,
+    /// How to become root if not connecting as root.
+    pub become_method: BecomeMethod,
+    /// Password for `become_method`, if it needs one.
+    pub become_password: Option<String>,
+}
+
+/// Check a `--preinstall-file` or `--postinstall-file` argument.
+pub fn hook_file(path: &str) -> Result<String> {
+    if Path::new(path).is_file() {
+        Ok(path.into())
+    } else {
+        Err(Error::Bootstrap(format!("Hook script {} is not a file", path)))
+    }
+}
+
+/// The agent version to install: `version` if given, otherwise the
+/// project's "agent_version", if pinned.
+pub fn agent_version<P: AsRef<Path>>(project_path: P, version: Option<&str>) -> Result<Option<String>> {
+    if let Some(v) = version {
+        return Ok(Some(v.into()));
+    }
+
+    match project::read_conf_key(project_path, "agent_version")? {
+        Some(Value::String(v)) => Ok(Some(v)),
+        Some(v) => Err(Error::Bootstrap(format!("\"agent_version\" must be a string, got {}", v))),
+        None => Ok(None),
+    }
+}
+
+/// Whether agent `version` implements API `api_version`, i.e. their
+/// major and minor versions match.
+pub fn is_api_compatible(version: &str, api_version: &str) -> bool {
+    let major_minor: Vec<&str> = version.trim_left_matches('v').splitn(3, '.').take(2).collect();
+    major_minor.join(".") == api_version
+}
+
+/// Parse an `--upload local:remote` argument.
+pub fn parse_upload(upload: &str) -> Result<(String, String)> {
+    let mut parts = upload.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(local), Some(remote)) if !local.is_empty() && !remote.is_empty() => {
+            if !Path::new(local).is_file() {
+                return Err(Error::Bootstrap(format!("Upload source {} is not a file", local)));
+            }
+            Ok((local.into(), remote.into()))
+        },
+        _ => Err(Error::Bootstrap(format!("Invalid upload \"{}\", expected local:remote", upload))),
+    }
+}
+
+/// Seconds to wait for the agent to answer a health check.
+const HEALTH_CHECK_TIMEOUT: u64 = 10;
+
+// Prints the last lines of the agent's log, wherever the init system
+// put it.
+const AGENT_LOG_SOURCE: &'static str = "#!/bin/sh
+{{SUDO}} journalctl -u inagent -n 20 --no-pager 2>/dev/null ||
+    {{SUDO}} tail -n 20 /var/log/inagent.log 2>/dev/null ||
+    {{SUDO}} grep inagent /var/log/messages /var/log/syslog 2>/dev/null | tail -n 20
+";
+
+const PLACEHOLDER_CERT: &'static str = "#   ****  PLACEHOLDER - NOT A REAL CERTIFICATE  ****
+#   A new host certificate will be created on the auth server and
+#   inserted here when the host is bootstrapped.
+metadata
+curve
+    public-key = \"<HOST PUBLIC KEY>\"
+    secret-key = \"<HOST SECRET KEY>\"";
+
+const PLACEHOLDER_BUNDLE: &'static str = "<UPLOADED AGENT BUNDLE>";
+
+const PLACEHOLDER_PREINSTALL: &'static str = "<UPLOADED PREINSTALL SCRIPT> || exit 1";
+
+const PLACEHOLDER_POSTINSTALL: &'static str = "<UPLOADED POSTINSTALL SCRIPT> || exit 1";
+
+const PLACEHOLDER_PASSWORD: &'static str = "<BECOME PASSWORD>";
+
 pub struct Bootstrap {
     hostname: String,
-    _stream: TcpStream,
-    session: Session,
+    transport: Box<Transport>,
     is_root: bool,
+    /// Cached result of checking for passwordless privilege escalation
+    passwordless: Option<bool>,
 }
 
 impl Bootstrap {
     pub fn new(hostname: &str,
                port: Option<u32>,
-               username: Option<&str>,
-               password: Option<&str>,
-               identity_file: Option<&str>) -> Result<Bootstrap> {
-        let tcp = TcpStream::connect(&*format!("{}:{}", hostname, port.unwrap_or(22)))?;
-        let mut sess = Session::new().unwrap();
-        sess.handshake(&tcp)?;
-
-        let u = username.unwrap_or("root");
-        if let Some(ref i) = identity_file {
-            sess.userauth_pubkey_file(u, None, Path::new(i), None)?;
-        }
-        else if let Some(ref p) = password {
-            sess.userauth_password(u, p).unwrap();
+               credentials: &Credentials,
+               host_key_check: &HostKeyCheck,
+               jump_hosts: &[JumpHost],
+               timeouts: &Timeouts) -> Result<Bootstrap> {
+        let transport = SshTransport::connect(hostname, port.unwrap_or(22) as u16, credentials, host_key_check, jump_hosts, timeouts)?;
+
+        Ok(Bootstrap {
+            hostname: hostname.into(),
+            transport: Box::new(transport),
+            is_root: credentials.username() == "root",
+            passwordless: None,
+        })
+    }
+
+    /// Bootstrap `hostname` over an already established transport,
+    /// e.g. the local machine or a container.
+    pub fn with_transport(hostname: &str, mut transport: Box<Transport>) -> Result<Bootstrap> {
+        let uid = transport.exec("id -u")?;
+
+        Ok(Bootstrap {
+            hostname: hostname.into(),
+            transport: transport,
+            is_root: uid.trim() == "0",
+            passwordless: None,
+        })
+    }
+
+    /// Create a host cert and install the agent. If installation
+    /// fails, the newly created cert is deleted from the auth server
+    /// so that bootstrap can be retried.
+    ///
+    /// Bootstrapping a host that already has a cert and a running agent
+    /// does nothing, so it's safe to rerun against a whole inventory.
+    /// If the agent is installed but the host has no cert, the agent is
+    /// reconfigured with a new one.
+    pub fn run(&mut self, opts: &InstallOptions) -> Result<()> {
+        let report = self.preflight(opts)?;
+
+        let mut auth = try!(Auth::new(&env::current_dir().unwrap()));
+        let cert_path = format!("{}.crt", self.hostname);
+
+        let has_cert = !opts.reuse_cert && try!(auth.list("host")).contains(&self.hostname);
+        if has_cert && report.agent_running {
+            println!("Agent is already installed and running on {}; skipping install", self.hostname);
+            self.create_host_data()?;
+            return self.verify_agent(opts);
+        }
+        else if has_cert {
+            return Err(Error::Bootstrap(format!("{0} already has a certificate, but its agent is not running. Run `incli host rekey {0}` to reinstall it with a new certificate.", self.hostname)));
+        }
+        else if report.agent_installed && !opts.reuse_cert {
+            println!("Agent is already installed on {}; reconfiguring it with a new certificate", self.hostname);
+        }
+
+        // Catch template errors before creating a cert that would be
+        // left behind
+        let sudo = self.sudo(opts)?;
+        let template = Template::load(&env::current_dir()?, BOOTSTRAP_SOURCE)?;
+        render(&template, &self.hostname, PLACEHOLDER_CERT, opts.agent_bundle.as_ref().map(|_| PLACEHOLDER_BUNDLE), &sudo, opts)?;
+
+        if opts.reuse_cert {
+            let agent_cert = Cert::new(ZCert::load(&cert_path)?);
+            self.install(&agent_cert, opts)?;
         } else {
-            sess.userauth_agent(u)?;
+            let agent_cert = try!(auth.add("host", &self.hostname, &[]));
+            self.install_or_rollback(&mut auth, &agent_cert, &cert_path, opts)?;
         }
 
-        if sess.authenticated() {
-            Ok(Bootstrap {
-                hostname: hostname.into(),
-                _stream: tcp,
-                session: sess,
-                is_root: u == "root",
-            })
+        self.create_host_data()?;
+        self.verify_agent(opts)?;
+
+        // The host has its secret key now, so don't leave a copy here
+        if opts.reuse_cert {
+            fs::remove_file(&cert_path)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the agent answers API requests from this machine,
+    /// including the end of the agent's log if it doesn't.
+    fn verify_agent(&mut self, opts: &InstallOptions) -> Result<()> {
+        let reason = match check_agent(&self.hostname) {
+            Ok(()) => return Ok(()),
+            Err(reason) => reason,
+        };
+
+        let log = self.sudo(opts)
+                      .and_then(|sudo| self.exec_script_output(&AGENT_LOG_SOURCE.replace("{{SUDO}}", &sudo)))
+                      .unwrap_or_else(|e| format!("(could not read agent log: {})", e));
+        Err(Error::Bootstrap(format!("Agent on {} is not responding: {}\nAgent log:\n{}", self.hostname, reason, log.trim_right())))
+    }
+
+    /// Seed the host's data file from facts gathered on the host, so
+    /// that it can be targeted by `incli run` straight away. An
+    /// existing data file is left alone.
+    fn create_host_data(&mut self) -> Result<()> {
+        let path = hostdata::path(&env::current_dir().unwrap(), &self.hostname);
+        if path.exists() {
+            return Ok(());
+        }
+
+        let output = self.channel_exec(&format!("/bin/sh -c {}", shell_quote(hostdata::FACTS_SOURCE)))?;
+        let facts = Facts::parse(&output);
+        hostdata::create(&path, &self.hostname, &facts)
+    }
+
+    /// Probe the host and print a report, failing if anything would
+    /// stop the agent from installing.
+    pub fn preflight(&mut self, opts: &InstallOptions) -> Result<Report> {
+        let report = self.probe(opts)?;
+        let problems = report.problems(opts.agent_bundle.is_some());
+        if problems.is_empty() {
+            Ok(report)
         } else {
-            Err(Error::Bootstrap("Failed to authenticate to host".into()))
+            Err(PreflightError::Failed(self.hostname.clone(), problems).into())
         }
     }
 
-    pub fn run(&mut self, preinstall_script: Option<&str>, postinstall_script: Option<&str>) -> Result<()> {
-        let mut auth = try!(Auth::new(&env::current_dir().unwrap()));
-        let agent_cert = try!(auth.add("host", &self.hostname));
+    /// Probe the host and print a report, each line prefixed with the
+    /// hostname.
+    fn probe(&mut self, opts: &InstallOptions) -> Result<Report> {
+        let output = self.channel_exec(&format!("/bin/sh -c {}", shell_quote(preflight::PREFLIGHT_SOURCE)))?;
+        let mut report = Report::parse(&output);
+        report.escalation = self.escalation(opts)?;
+
+        // Hold the lock so that reports from parallel runs don't mix
+        let stdout = io::stdout();
+        let mut writer = PrefixWriter::new(&format!("[{}]", self.hostname), stdout.lock());
+        writeln!(writer, "Preflight checks:\n{}", report)?;
+        writer.flush()?;
+
+        Ok(report)
+    }
+
+    /// Whether we need a password to become root using `method`.
+    pub fn needs_become_password(&mut self, method: BecomeMethod) -> Result<bool> {
+        if self.is_root {
+            return Ok(false);
+        }
 
-        // As we are in a project directory, it's safe to assume that
-        // the auth public key must be present.
-        let mut fh = File::open("auth.crt")?;
-        let mut auth_cert = String::new();
-        fh.read_to_string(&mut auth_cert)?;
+        if self.passwordless.is_none() {
+            let passwordless = match method.check_cmd() {
+                Some(cmd) => self.channel_exec(cmd).is_ok(),
+                None => false,
+            };
+            self.passwordless = Some(passwordless);
+        }
+
+        Ok(!self.passwordless.unwrap())
+    }
 
-        // Load project config
-        let conf: ProjectConfig = read_conf(project::CONFIGNAME)?;
+    /// How to become root, or None if we can't (or needn't).
+    fn escalation(&mut self, opts: &InstallOptions) -> Result<Option<Escalation>> {
+        match opts.become_password {
+            Some(ref p) => Ok(Some(Escalation::new(opts.become_method, Some(p))?)),
+            None if self.is_root || self.needs_become_password(opts.become_method)? => Ok(None),
+            None => Ok(Some(Escalation::new(opts.become_method, None)?)),
+        }
+    }
+
+    /// The command prefix for running commands as root.
+    fn sudo(&mut self, opts: &InstallOptions) -> Result<String> {
+        if self.is_root {
+            return Ok(String::new());
+        }
+
+        match self.escalation(opts)? {
+            Some(e) => Ok(e.prefix()),
+            None => Err(PrivilegeError::Unavailable(opts.become_method.name()).into()),
+        }
+    }
+
+    /// Install a newly created cert, deleting it from the auth server
+    /// if installation fails.
+    fn install_or_rollback(&mut self, auth: &mut Auth, agent_cert: &Cert, cert_path: &str, opts: &InstallOptions) -> Result<()> {
+        match self.install(agent_cert, opts) {
+            Ok(()) => Ok(()),
+            Err(e) => match auth.delete(&self.hostname) {
+                Ok(()) => Err(e),
+                Err(rollback_err) => {
+                    // Keep the secret key so the user can retry with
+                    // --reuse-cert.
+                    let saved = match agent_cert.save_secret(cert_path) {
+                        Ok(()) => format!("Certificate saved to {}; retry with --reuse-cert", cert_path),
+                        Err(save_err) => format!("Could not save certificate: {}", save_err),
+                    };
+                    Err(Error::Bootstrap(format!("{}\nAdditionally, could not delete host certificate: {}\n{}", e, rollback_err, saved)))
+                },
+            },
+        }
+    }
+
+    fn install(&mut self, agent_cert: &Cert, opts: &InstallOptions) -> Result<()> {
+        let bundle_path = self.upload_bundle(opts)?;
+
+        // Copy any extra files, keeping their permissions
+        for &(ref local, ref remote) in &opts.uploads {
+            let mode = fs::metadata(local)?.permissions().mode() & 0o777;
+            self.upload(Path::new(local), remote, mode as i32)?;
+        }
+
+        // Hook scripts are run by path, so that they get the
+        // interpreter named in their shebang
+        let hook_dir = if opts.preinstall_file.is_some() || opts.postinstall_file.is_some() {
+            let dir = self.channel_exec("/bin/sh -c \"mktemp -d 2>/dev/null || mktemp -d -t in-hooks\"")?;
+            Some(dir.trim().to_owned())
+        } else {
+            None
+        };
+
+        let result = self.run_bootstrap_script(agent_cert, bundle_path.as_ref().map(|p| &**p), hook_dir.as_ref().map(|d| &**d), opts);
+
+        match hook_dir {
+            Some(dir) => {
+                let cleanup = self.channel_exec(&format!("rm -rf {}", shell_quote(&dir)));
+                result.and(cleanup.map(|_| ()))
+            },
+            None => result,
+        }
+    }
+
+    fn run_bootstrap_script(&mut self, agent_cert: &Cert, bundle_path: Option<&str>, hook_dir: Option<&str>, opts: &InstallOptions) -> Result<()> {
+        let mut opts = opts.clone();
+        if let Some(dir) = hook_dir {
+            if let Some(ref local) = opts.preinstall_file {
+                opts.preinstall = Some(self.upload_hook(local, &format!("{}/preinstall", dir))?);
+            }
+            if let Some(ref local) = opts.postinstall_file {
+                opts.postinstall = Some(self.upload_hook(local, &format!("{}/postinstall", dir))?);
+            }
+        }
 
         // Install and run bootstrap script
-        let script = BOOTSTRAP_SOURCE.replace("{{AGENTCERT}}", &agent_cert.secret())
-                                     .replace("{{AUTHCERT}}", &auth_cert)
-                                     .replace("{{AUTHHOST}}", &conf.auth_server)
-                                     .replace("{{AUTHPORT}}", &conf.auth_update_port.to_string())
-                                     .replace("{{PREINSTALL}}", preinstall_script.unwrap_or(""))
-                                     .replace("{{POSTINSTALL}}", postinstall_script.unwrap_or(""))
-                                     .replace("{{SUDO}}", if self.is_root { "" } else { "sudo" });
-        let bootstrap_path = self.channel_exec("/bin/sh -c \"mktemp 2>/dev/null || mktemp -t in-bootstrap\"")?;
-        // Deliberately omit terminating EOS delimiter as it breaks
-        // FreeBSD and works fine without it.
-        let cmd = format!("chmod u+x {0} && cat << \"EOS\" > {0}
-{1}", bootstrap_path.trim(), script);
-        self.channel_exec(&cmd)?;
-        self.channel_exec(&bootstrap_path)?;
+        let sudo = self.sudo(&opts)?;
+        let template = Template::load(&env::current_dir()?, BOOTSTRAP_SOURCE)?;
+        let script = render(&template, &self.hostname, &agent_cert.secret(), bundle_path, &sudo, &opts)?;
+        self.exec_script(&script)
+    }
+
+    /// Copy a hook script to `remote_path`, returning the shell that
+    /// runs it.
+    fn upload_hook(&mut self, local_path: &str, remote_path: &str) -> Result<String> {
+        let mut script = Vec::new();
+        File::open(local_path)?.read_to_end(&mut script)?;
+        self.transport.write_file(&script, remote_path, 0o700)?;
+        Ok(format!("{} || exit 1", shell_quote(remote_path)))
+    }
+
+    /// Upload the agent bundle for offline installs, returning its
+    /// remote path.
+    fn upload_bundle(&mut self, opts: &InstallOptions) -> Result<Option<String>> {
+        match opts.agent_bundle {
+            Some(ref bundle) => {
+                let path = self.channel_exec("/bin/sh -c \"mktemp 2>/dev/null || mktemp -t in-agent\"")?;
+                self.upload(Path::new(bundle), path.trim(), 0o600)?;
+                Ok(Some(path.trim().to_owned()))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Replace the host's cert with a new one and restart the agent.
+    ///
+    /// The auth server holds one cert per name, so the old cert has to
+    /// be deleted before its replacement can be created. Everything
+    /// that can be checked beforehand is, and the new cert is saved
+    /// locally until the agent is answering with it, so a failed rekey
+    /// can be retried with `reuse_cert`.
+    pub fn rekey(&mut self, opts: &InstallOptions) -> Result<()> {
+        let report = self.probe(opts)?;
+        // Nothing is downloaded, so there's no need for curl
+        let mut problems = report.problems(true);
+        if !report.agent_installed {
+            problems.push("agent is not installed".into());
+        }
+        if !problems.is_empty() {
+            return Err(PreflightError::Failed(self.hostname.clone(), problems).into());
+        }
+
+        let sudo = self.sudo(opts)?;
+        let template = Template::new(&REKEY_SOURCE.replace("{{AGENTHELPERS}}", AGENT_HELPERS_SOURCE));
+        // Catch template errors before touching the auth server
+        render(&template, &self.hostname, PLACEHOLDER_CERT, None, &sudo, opts)?;
+
+        let cert_path = format!("{}.crt", self.hostname);
+        let agent_cert = if opts.reuse_cert {
+            Cert::new(ZCert::load(&cert_path)?)
+        } else {
+            let mut auth = try!(Auth::new(&env::current_dir().unwrap()));
+            auth.delete(&self.hostname)?;
+            let cert = auth.add("host", &self.hostname, &[]).map_err(|e| {
+                Error::Bootstrap(format!("{}\nThe old certificate for {1} was deleted, but the agent is still using it. Run `incli host rekey {1}` again.", e, self.hostname))
+            })?;
+            cert.save_secret(&cert_path)?;
+            cert
+        };
+
+        let result = render(&template, &self.hostname, &agent_cert.secret(), None, &sudo, opts)
+                         .and_then(|script| self.exec_script(&script))
+                         .and_then(|_| self.verify_agent(opts));
+
+        match result {
+            Ok(()) => {
+                fs::remove_file(&cert_path)?;
+                Ok(())
+            },
+            Err(e) => Err(Error::Bootstrap(format!("{}\nNew certificate saved to {}; retry with --reuse-cert", e, cert_path))),
+        }
+    }
+
+    /// Replace the agent with the latest release, or the version in
+    /// `opts`, keeping the host's cert and config.
+    pub fn upgrade_agent(&mut self, opts: &InstallOptions) -> Result<()> {
+        let report = self.preflight(opts)?;
+        if !report.agent_installed {
+            return Err(Error::Bootstrap(format!("Agent is not installed on {}; bootstrap the host first", self.hostname)));
+        }
+        let sudo = self.sudo(opts)?;
+
+        let bundle_path = self.upload_bundle(opts)?;
+        let template = Template::new(&UPGRADE_SOURCE.replace("{{AGENTHELPERS}}", AGENT_HELPERS_SOURCE));
+        // The existing cert is kept, so there's none to install
+        let script = render(&template, &self.hostname, "", bundle_path.as_ref().map(|p| &**p), &sudo, opts)?;
+        self.exec_script(&script)
+    }
+
+    /// Stop and uninstall the agent, securely remove its certs, then
+    /// delete the host cert and the host's data file.
+    pub fn decommission(&mut self, opts: &InstallOptions) -> Result<()> {
+        let sudo = self.sudo(opts)?;
+        let script = DECOMMISSION_SOURCE.replace("{{AGENTHELPERS}}", AGENT_HELPERS_SOURCE)
+                                        .replace("{{SUDO}}", &sudo);
+        self.exec_script(&script)?;
+
+        let mut auth = try!(Auth::new(&env::current_dir().unwrap()));
+        auth.delete(&self.hostname)?;
+
+        let data_path = hostdata::path(&env::current_dir().unwrap(), &self.hostname);
+        if data_path.exists() {
+            fs::remove_file(&data_path)?;
+        }
 
         Ok(())
     }
 
-    fn channel_exec(&mut self, cmd: &str) -> Result<String> {
-        let mut channel = self.session.channel_session()?;
-        channel.exec(cmd)?;
-        channel.send_eof()?;
-        channel.wait_eof()?;
+    /// Copy `script` into a private temp dir on the host and run it.
+    /// Scripts may embed the host's secret key, so the script is
+    /// overwritten and removed afterwards, whether or not it succeeded.
+    fn exec_script(&mut self, script: &str) -> Result<()> {
+        self.with_script(script, |me, path| me.channel_exec_stream(path))
+    }
 
-        let mut out = String::new();
-        channel.read_to_string(&mut out)?;
+    /// Run `script` as `exec_script` does, returning its output.
+    fn exec_script_output(&mut self, script: &str) -> Result<String> {
+        self.with_script(script, |me, path| me.channel_exec(path))
+    }
 
-        if channel.exit_status()? == 0 {
-            Ok(out)
+    fn with_script<T, F>(&mut self, script: &str, run: F) -> Result<T>
+        where F: FnOnce(&mut Bootstrap, &str) -> Result<T>
+    {
+        let dir = self.channel_exec("/bin/sh -c \"mktemp -d 2>/dev/null || mktemp -d -t in-bootstrap\"")?;
+        let dir = dir.trim();
+        let script_path = format!("{}/bootstrap.sh", dir);
+
+        let result = match self.transport.write_file(script.as_bytes(), &script_path, 0o700) {
+            Ok(()) => run(self, &script_path),
+            Err(e) => Err(e),
+        };
+
+        let cleanup = self.channel_exec(&format!("/bin/sh -c \"shred -u {0} 2>/dev/null || rm -P {0} 2>/dev/null || rm -f {0}; rmdir {1}\"", script_path, dir));
+        match (result, cleanup) {
+            (Ok(v), Ok(_)) => Ok(v),
+            (Ok(_), Err(e)) => Err(Error::Bootstrap(format!("Could not remove {}, which contains secrets: {}", script_path, e))),
+            (Err(e), Ok(_)) => Err(e),
+            (Err(e), Err(cleanup_err)) => Err(Error::Bootstrap(format!("{}\nAdditionally, could not remove {}, which contains secrets: {}", e, script_path, cleanup_err))),
+        }
+    }
+
+    /// Render the bootstrap script for `hostname` and `username`
+    /// without creating a host cert or connecting to the host. The host
+    /// cert and any remote paths are replaced with placeholders.
+    pub fn dry_run(hostname: &str, username: Option<&str>, opts: &InstallOptions) -> Result<String> {
+        let bundle_path = opts.agent_bundle.as_ref().map(|_| PLACEHOLDER_BUNDLE);
+        let mut opts = opts.clone();
+        if opts.preinstall_file.is_some() {
+            opts.preinstall = Some(PLACEHOLDER_PREINSTALL.into());
+        }
+        if opts.postinstall_file.is_some() {
+            opts.postinstall = Some(PLACEHOLDER_POSTINSTALL.into());
+        }
+        let sudo = if username.unwrap_or("root") == "root" {
+            String::new()
         } else {
-            let mut stderr = channel.stderr();
-            let mut err = String::new();
-            stderr.read_to_string(&mut err)?;
-            Err(Error::Bootstrap(format!("stdout: {}\nstderr: {}", out, err)))
+            let password = if opts.become_password.is_some() {
+                Some(PLACEHOLDER_PASSWORD)
+            } else {
+                None
+            };
+            Escalation::new(opts.become_method, password)?.prefix()
+        };
+        let template = Template::load(&env::current_dir()?, BOOTSTRAP_SOURCE)?;
+        render(&template, hostname, PLACEHOLDER_CERT, bundle_path, &sudo, &opts)
+    }
+
+    fn upload(&mut self, local_path: &Path, remote_path: &str, mode: i32) -> Result<()> {
+        self.transport.upload(local_path, remote_path, mode)
+    }
+
+    fn channel_exec(&mut self, cmd: &str) -> Result<String> {
+        self.transport.exec(cmd)
+    }
+
+    /// Execute `cmd`, printing its stdout and stderr line by line as
+    /// they arrive, prefixed with the hostname.
+    fn channel_exec_stream(&mut self, cmd: &str) -> Result<()> {
+        let prefix = format!("[{}]", self.hostname);
+        let mut stdout = PrefixWriter::new(&prefix, io::stdout());
+        let mut stderr = PrefixWriter::new(&prefix, io::stderr());
+
+        let status = self.transport.exec_stream(cmd, &mut stdout, &mut stderr)?;
+        stdout.flush()?;
+        stderr.flush()?;
+
+        match status {
+            0 => Ok(()),
+            status => Err(Error::Bootstrap(format!("Remote command exited with status {}", status))),
+        }
+    }
+}
+
+/// Connect to the agent over CURVE with the project's user cert, as
+/// `incli run` would, and run a trivial command.
+fn check_agent(hostname: &str) -> ::std::result::Result<(), String> {
+    let data_path = format!("hosts/{}.json", hostname);
+    let (tx, rx) = mpsc::channel();
+
+    // The API client blocks until the agent replies, so give up on it
+    // if it takes too long.
+    thread::spawn(move || {
+        let result = Host::connect(&data_path)
+                          .and_then(|mut host| inapi::Command::new("true").exec(&mut host));
+        let _ = tx.send(result.map(|r| r.exit_code).map_err(|e| e.to_string()));
+    });
+
+    match rx.recv_timeout(Duration::from_secs(HEALTH_CHECK_TIMEOUT)) {
+        Ok(Ok(0)) => Ok(()),
+        Ok(Ok(code)) => Err(format!("test command exited with status {}", code)),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(format!("no reply after {}s", HEALTH_CHECK_TIMEOUT)),
+    }
+}
+
+/// Writer that prefixes each complete line before passing it on.
+struct PrefixWriter<W: Write> {
+    prefix: String,
+    buf: Vec<u8>,
+    inner: W,
+}
+
+impl<W: Write> PrefixWriter<W> {
+    fn new(prefix: &str, inner: W) -> PrefixWriter<W> {
+        PrefixWriter {
+            prefix: prefix.into(),
+            buf: Vec::new(),
+            inner: inner,
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let line = String::from_utf8_lossy(line);
+        writeln!(self.inner, "{} {}", self.prefix, line.trim_right_matches('\r'))
+    }
+}
+
+impl<W: Write> Write for PrefixWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..pos + 1).collect();
+            self.write_line(&line[..pos])?;
+        }
+
+        Ok(data.len())
+    }
+
+    /// Writes out any trailing partial line.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            let line = mem::replace(&mut self.buf, Vec::new());
+            self.write_line(&line)?;
+        }
+        self.inner.flush()
+    }
+}
+
+fn render(template: &Template, hostname: &str, agent_cert: &str, bundle_path: Option<&str>, sudo: &str, opts: &InstallOptions) -> Result<String> {
+    // As we are in a project directory, it's safe to assume that
+    // the auth public key must be present.
+    let mut fh = File::open("auth.crt")?;
+    let mut auth_cert = String::new();
+    fh.read_to_string(&mut auth_cert)?;
+
+    // Load project config
+    let conf: ProjectConfig = read_conf(project::CONFIGNAME)?;
+
+    let install_agent = match bundle_path {
+        Some(path) => INSTALL_OFFLINE.replace("{{AGENTBUNDLE}}", path).replace("{{SUDO}}", sudo),
+        None => {
+            let version = opts.agent_version.as_ref().map(|v| format!(" {}", shell_quote(v))).unwrap_or_default();
+            INSTALL_ONLINE.replace("{{AGENTVERSION}}", &version)
+        },
+    };
+
+    let mut vars = HashMap::new();
+    vars.insert("AGENTCERT".into(), agent_cert.into());
+    vars.insert("AUTHCERT".into(), auth_cert);
+    vars.insert("AUTHHOST".into(), conf.auth_server);
+    vars.insert("AUTHPORT".into(), conf.auth_update_port.to_string());
+    vars.insert("HOSTNAME".into(), hostname.into());
+    vars.insert("INSTALLAGENT".into(), install_agent);
+    vars.insert("POSTINSTALL".into(), opts.postinstall.clone().unwrap_or_default());
+    vars.insert("PREINSTALL".into(), opts.preinstall.clone().unwrap_or_default());
+    vars.insert("SUDO".into(), sudo.into());
+    for &(ref key, ref value) in &opts.vars {
+        vars.insert(key.clone(), value.clone());
+    }
+
+    template.render(&vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use auth::Auth;
+    use cert::Cert;
+    use czmq::{ZCert, ZMsg, ZSys};
+    use std::fs::File;
+    use std::io::Write;
+    use std::thread::spawn;
+    use super::{hook_file, is_api_compatible, parse_upload, Bootstrap, InstallOptions, PrefixWriter};
+    use tempdir::TempDir;
+    use transport::LocalTransport;
+
+    #[test]
+    fn test_channel_exec_stream() {
+        let mut bootstrap = Bootstrap::with_transport("localhost", Box::new(LocalTransport::new())).unwrap();
+        assert!(bootstrap.channel_exec_stream("echo installing").is_ok());
+        assert!(bootstrap.channel_exec_stream("exit 2").is_err());
+    }
+
+    #[test]
+    fn test_exec_script() {
+        let mut bootstrap = Bootstrap::with_transport("localhost", Box::new(LocalTransport::new())).unwrap();
+        assert!(bootstrap.exec_script("#!/bin/sh\ntest -x \"$0\"\n").is_ok());
+        assert!(bootstrap.exec_script("#!/bin/sh\nexit 1\n").is_err());
+        assert_eq!(bootstrap.exec_script_output("#!/bin/sh\necho ok\n").unwrap(), "ok\n");
+    }
+
+    #[test]
+    fn test_install_or_rollback() {
+        ZSys::init();
+
+        let (client, mut server) = ZSys::create_pipe().unwrap();
+
+        let handle = spawn(move|| {
+            let req = ZMsg::recv(&mut server).unwrap();
+            assert_eq!(&req.popstr().unwrap().unwrap(), "cert::delete");
+            assert_eq!(&req.popstr().unwrap().unwrap(), "localhost");
+            server.send_str("Ok").unwrap();
+
+            let req = ZMsg::recv(&mut server).unwrap();
+            assert_eq!(&req.popstr().unwrap().unwrap(), "cert::delete");
+            let rep = ZMsg::new();
+            rep.addstr("Err").unwrap();
+            rep.addstr("I'm broke!").unwrap();
+            rep.send(&mut server).unwrap();
+        });
+
+        let dir = TempDir::new("bootstrap_test_install_or_rollback").unwrap();
+        let cert_path = dir.path().join("localhost.crt");
+        let cert_path = cert_path.to_str().unwrap();
+
+        let mut auth = Auth::with_sock(client);
+        let agent_cert = Cert::new(ZCert::new().unwrap());
+        let mut opts = InstallOptions::default();
+        opts.preinstall = Some("exit 1".into());
+
+        let mut bootstrap = Bootstrap::with_transport("localhost", Box::new(LocalTransport::new())).unwrap();
+
+        // The cert is deleted, so there's nothing to keep
+        assert!(bootstrap.install_or_rollback(&mut auth, &agent_cert, cert_path, &opts).is_err());
+        assert!(!dir.path().join("localhost.crt").exists());
+
+        // The cert couldn't be deleted, so its secret is kept for
+        // --reuse-cert
+        assert!(bootstrap.install_or_rollback(&mut auth, &agent_cert, cert_path, &opts).is_err());
+        assert!(dir.path().join("localhost.crt").exists());
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_is_api_compatible() {
+        assert!(is_api_compatible("0.3.2", "0.3"));
+        assert!(is_api_compatible("v0.3", "0.3"));
+        assert!(!is_api_compatible("0.4.0", "0.3"));
+        assert!(!is_api_compatible("0.30.1", "0.3"));
+    }
+
+    #[test]
+    fn test_hook_file() {
+        let dir = TempDir::new("test_hook_file").unwrap();
+        let path = dir.path().join("harden.sh");
+        File::create(&path).unwrap();
+
+        assert_eq!(hook_file(path.to_str().unwrap()).unwrap(), path.to_str().unwrap());
+        assert!(hook_file("echo hi").is_err());
+    }
+
+    #[test]
+    fn test_upload_hook() {
+        let dir = TempDir::new("test_upload_hook").unwrap();
+        let local = dir.path().join("harden.sh");
+        let mut fh = File::create(&local).unwrap();
+        fh.write_all(b"#!/bin/sh\necho hardened\n").unwrap();
+        let remote = dir.path().join("preinstall");
+
+        let mut bootstrap = Bootstrap::with_transport("localhost", Box::new(LocalTransport::new())).unwrap();
+        let hook = bootstrap.upload_hook(local.to_str().unwrap(), remote.to_str().unwrap()).unwrap();
+        assert_eq!(hook, format!("'{}' || exit 1", remote.display()));
+        assert_eq!(bootstrap.channel_exec(&hook).unwrap(), "hardened\n");
+    }
+
+    #[test]
+    fn test_parse_upload() {
+        let dir = TempDir::new("test_parse_upload").unwrap();
+        let path = dir.path().join("ca.pem");
+        File::create(&path).unwrap();
+
+        let local = path.to_str().unwrap();
+        assert_eq!(parse_upload(&format!("{}:/tmp/ca.pem", local)).unwrap(), (local.to_owned(), "/tmp/ca.pem".to_owned()));
+        assert!(parse_upload(local).is_err());
+        assert!(parse_upload("/no/such/file:/tmp/x").is_err());
+    }
+
+    #[test]
+    fn test_prefix_writer() {
+        let mut out = Vec::new();
+        {
+            let mut writer = PrefixWriter::new("[web1]", &mut out);
+            writer.write_all(b"Installing ag").unwrap();
+            writer.write_all(b"ent\r\nStarting daemon\nDone").unwrap();
+            writer.flush().unwrap();
         }
+        assert_eq!(String::from_utf8(out).unwrap(), "[web1] Installing agent\n[web1] Starting daemon\n[web1] Done\n");
     }
 }
@@ -7,6 +7,8 @@
 // modified, or distributed except according to those terms.
 
 use czmq::ZCert;
+use error::Result;
+use std::{error, fmt};
 use std::ops::Deref;
 use time;
 
@@ -57,9 +59,97 @@ impl Cert {
 ", time::now().strftime("%F %T").unwrap(), cert_type, secret_warning)
     }
 
-    // TODO: Need to handle metadata
+    /// Metadata as sorted (key, value) pairs.
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        metadata(&self.zcert)
+    }
+
+    /// Write the cert's metadata as a ZPL "metadata" section.
     fn add_metadata(&self, header: &mut String) {
         header.push_str("metadata\n");
+        for (key, value) in self.metadata() {
+            header.push_str(&format!("    {} = \"{}\"\n", key, value));
+        }
+    }
+}
+
+/// The public details of a cert, as the auth server gives them out.
+#[derive(Debug, PartialEq)]
+pub struct CertInfo {
+    pub name: String,
+    pub public_key: String,
+    /// Sorted (key, value) pairs, if the auth server sent them
+    pub metadata: Option<Vec<(String, String)>>,
+}
+
+impl CertInfo {
+    /// A human readable summary of the cert.
+    pub fn details(&self) -> String {
+        let created = self.metadata.as_ref()
+                                   .and_then(|m| m.iter().find(|&&(ref k, _)| k == "created"))
+                                   .map(|&(_, ref v)| &**v)
+                                   .unwrap_or("unknown");
+        let mut details = format!("Name:        {}
+Created:     {}
+Public key:  {}
+Metadata:", self.name, created, self.public_key);
+
+        match self.metadata {
+            Some(ref meta) if meta.is_empty() => details.push_str("    none"),
+            Some(ref meta) => for &(ref key, ref value) in meta {
+                details.push_str(&format!("\n    {} = {}", key, value));
+            },
+            None => details.push_str("    unavailable (not sent by the auth server)"),
+        }
+
+        details
+    }
+}
+
+/// A cert's metadata as sorted (key, value) pairs.
+pub fn metadata(zcert: &ZCert) -> Vec<(String, String)> {
+    let mut meta: Vec<(String, String)> = zcert.meta_keys().map(|key| {
+        let value = match zcert.meta(key) {
+            Some(Ok(v)) => v,
+            Some(Err(bytes)) => String::from_utf8_lossy(&bytes).into_owned(),
+            None => String::new(),
+        };
+        (key.to_owned(), value)
+    }).collect();
+    meta.sort();
+    meta
+}
+
+/// Parse a `--meta key=value` argument. Values are written into cert
+/// files as quoted strings, so can't contain quotes or newlines.
+pub fn parse_meta(meta: &str) -> Result<(String, String)> {
+    let mut parts = meta.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(k), Some(v)) if !k.is_empty() &&
+                              k.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') &&
+                              !v.contains(|c: char| c == '"' || c == '\n' || c == '\r') => Ok((k.into(), v.into())),
+        _ => Err(CertError::InvalidMeta(meta.into()).into()),
+    }
+}
+
+#[derive(Debug)]
+pub enum CertError {
+    InvalidMeta(String),
+}
+
+impl fmt::Display for CertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CertError::InvalidMeta(ref m) => write!(f, "Invalid metadata \"{}\", expected key=value without quotes", m),
+        }
+    }
+}
+
+impl error::Error for CertError {
+    fn description(&self) -> &str {
+        match *self {
+            CertError::InvalidMeta(_) => "Invalid certificate metadata",
+        }
     }
 }
 
@@ -89,6 +179,19 @@ mod tests {
         cert.secret();
     }
 
+    #[test]
+    fn test_details() {
+        let mut info = CertInfo {
+            name: "web1".into(),
+            public_key: "abc".into(),
+            metadata: None,
+        };
+        assert_eq!(info.details(), "Name:        web1\nCreated:     unknown\nPublic key:  abc\nMetadata:    unavailable (not sent by the auth server)");
+
+        info.metadata = Some(vec![("created".into(), "2017-03-01 12:00:00".into()), ("type".into(), "host".into())]);
+        assert_eq!(info.details(), "Name:        web1\nCreated:     2017-03-01 12:00:00\nPublic key:  abc\nMetadata:\n    created = 2017-03-01 12:00:00\n    type = host");
+    }
+
     #[test]
     fn test_header() {
         let cert = Cert::new(ZCert::new().unwrap());
@@ -98,7 +201,19 @@ mod tests {
     #[test]
     fn test_add_metadata() {
         let cert = Cert::new(ZCert::new().unwrap());
+        cert.set_meta("type", "host");
+        cert.set_meta("creator", "ops");
         let mut header = String::new();
         cert.add_metadata(&mut header);
+        assert_eq!(header, "metadata\n    creator = \"ops\"\n    type = \"host\"\n");
+    }
+
+    #[test]
+    fn test_parse_meta() {
+        assert_eq!(parse_meta("role=web=1").unwrap(), ("role".into(), "web=1".into()));
+        assert!(parse_meta("role").is_err());
+        assert!(parse_meta("=web").is_err());
+        assert!(parse_meta("my role=web").is_err());
+        assert!(parse_meta("role=\"web\"").is_err());
     }
 }
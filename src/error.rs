@@ -7,16 +7,24 @@
 // modified, or distributed except according to those terms.
 
 use auth;
+use cert::CertError;
 use czmq;
+use hostkey::HostKeyError;
 use inapi;
+use inventory::InventoryError;
 use language::LanguageError;
 use payload::PayloadError;
+use preflight::PreflightError;
+use privilege::PrivilegeError;
 use project::ProjectError;
 use rustc_serialize::json::{DecoderError, EncoderError};
+use secret::SecretError;
 use serde_json;
+use ssh::SshError;
 use ssh2;
 use std::{error, fmt, io, result, string};
 use std::convert::From;
+use template::TemplateError;
 use zdaemon;
 
 pub type Result<T> = result::Result<T, Error>;
@@ -25,17 +33,25 @@ pub type Result<T> = result::Result<T, Error>;
 pub enum Error {
     Auth(auth::Error),
     Bootstrap(String),
+    Cert(CertError),
     Czmq(czmq::Error),
     Decoder(DecoderError),
     Encoder(EncoderError),
+    HostKey(HostKeyError),
     Inapi(inapi::Error),
+    Inventory(InventoryError),
     Io(io::Error),
     Language(LanguageError),
     Payload(PayloadError),
+    Preflight(PreflightError),
+    Privilege(PrivilegeError),
     Project(ProjectError),
+    Secret(SecretError),
     SerdeJson(serde_json::Error),
+    Ssh(SshError),
     Ssh2(ssh2::Error),
     StringConvert(string::FromUtf8Error),
+    Template(TemplateError),
     ZDaemon(zdaemon::Error),
 }
 
@@ -44,17 +60,25 @@ impl fmt::Display for Error {
         match *self {
             Error::Auth(ref e) => write!(f, "Auth error: {}", e),
             Error::Bootstrap(ref e) => write!(f, "Bootstrap error: {}", e),
+            Error::Cert(ref e) => write!(f, "Certificate error: {}", e),
             Error::Czmq(ref e) => write!(f, "CZMQ error: {}", e),
             Error::Decoder(ref e) => write!(f, "Decoder error: {}", e),
             Error::Encoder(ref e) => write!(f, "Encoder error: {}", e),
+            Error::HostKey(ref e) => write!(f, "Host key error: {}", e),
             Error::Inapi(ref e) => write!(f, "Intecture API error: {}", e),
+            Error::Inventory(ref e) => write!(f, "Inventory error: {}", e),
             Error::Io(ref e) => write!(f, "IO error: {}", e),
             Error::Language(ref e) => write!(f, "Language error: {}", e),
             Error::Payload(ref e) => write!(f, "Payload error: {}", e),
+            Error::Preflight(ref e) => write!(f, "Preflight error: {}", e),
+            Error::Privilege(ref e) => write!(f, "Privilege error: {}", e),
             Error::Project(ref e) => write!(f, "Project error: {}", e),
+            Error::Secret(ref e) => write!(f, "Secret error: {}", e),
             Error::SerdeJson(ref e) => write!(f, "Serde JSON error: {}", e),
+            Error::Ssh(ref e) => write!(f, "SSH error: {}", e),
             Error::Ssh2(ref e) => write!(f, "SSH2 error: {}", e),
             Error::StringConvert(ref e) => write!(f, "String conversion error: {}", e),
+            Error::Template(ref e) => write!(f, "Template error: {}", e),
             Error::ZDaemon(ref e) => write!(f, "ZDaemon error: {}", e),
         }
     }
@@ -65,17 +89,25 @@ impl error::Error for Error {
         match *self {
             Error::Auth(ref e) => e.description(),
             Error::Bootstrap(ref e) => e,
+            Error::Cert(ref e) => e.description(),
             Error::Czmq(ref e) => e.description(),
             Error::Decoder(ref e) => e.description(),
             Error::Encoder(ref e) => e.description(),
+            Error::HostKey(ref e) => e.description(),
             Error::Inapi(ref e) => e.description(),
+            Error::Inventory(ref e) => e.description(),
             Error::Io(ref e) => e.description(),
             Error::Language(ref e) => e.description(),
             Error::Payload(ref e) => e.description(),
+            Error::Preflight(ref e) => e.description(),
+            Error::Privilege(ref e) => e.description(),
             Error::Project(ref e) => e.description(),
+            Error::Secret(ref e) => e.description(),
             Error::SerdeJson(ref e) => e.description(),
+            Error::Ssh(ref e) => e.description(),
             Error::Ssh2(ref e) => e.description(),
             Error::StringConvert(ref e) => e.description(),
+            Error::Template(ref e) => e.description(),
             Error::ZDaemon(ref e) => e.description(),
         }
     }
@@ -84,17 +116,25 @@ impl error::Error for Error {
         match *self {
             Error::Auth(ref e) => Some(e),
             Error::Bootstrap(_) => None,
+            Error::Cert(ref e) => Some(e),
             Error::Czmq(ref e) => Some(e),
             Error::Decoder(ref e) => Some(e),
             Error::Encoder(ref e) => Some(e),
+            Error::HostKey(ref e) => Some(e),
             Error::Inapi(ref e) => Some(e),
+            Error::Inventory(ref e) => Some(e),
             Error::Io(ref e) => Some(e),
             Error::Language(ref e) => Some(e),
             Error::Payload(ref e) => Some(e),
+            Error::Preflight(ref e) => Some(e),
+            Error::Privilege(ref e) => Some(e),
             Error::Project(ref e) => Some(e),
+            Error::Secret(ref e) => Some(e),
             Error::SerdeJson(ref e) => Some(e),
+            Error::Ssh(ref e) => Some(e),
             Error::Ssh2(ref e) => Some(e),
             Error::StringConvert(ref e) => Some(e),
+            Error::Template(ref e) => Some(e),
             Error::ZDaemon(ref e) => Some(e),
         }
     }
@@ -106,6 +146,12 @@ impl From<auth::Error> for Error {
     }
 }
 
+impl From<CertError> for Error {
+    fn from(err: CertError) -> Error {
+        Error::Cert(err)
+    }
+}
+
 impl From<czmq::Error> for Error {
     fn from(err: czmq::Error) -> Error {
         Error::Czmq(err)
@@ -124,12 +170,24 @@ impl From<EncoderError> for Error {
     }
 }
 
+impl From<HostKeyError> for Error {
+    fn from(err: HostKeyError) -> Error {
+        Error::HostKey(err)
+    }
+}
+
 impl From<inapi::Error> for Error {
     fn from(err: inapi::Error) -> Error {
         Error::Inapi(err)
     }
 }
 
+impl From<InventoryError> for Error {
+    fn from(err: InventoryError) -> Error {
+        Error::Inventory(err)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
         Error::Io(err)
@@ -148,18 +206,42 @@ impl From<PayloadError> for Error {
     }
 }
 
+impl From<PreflightError> for Error {
+    fn from(err: PreflightError) -> Error {
+        Error::Preflight(err)
+    }
+}
+
+impl From<PrivilegeError> for Error {
+    fn from(err: PrivilegeError) -> Error {
+        Error::Privilege(err)
+    }
+}
+
 impl From<ProjectError> for Error {
     fn from(err: ProjectError) -> Error {
         Error::Project(err)
     }
 }
 
+impl From<SecretError> for Error {
+    fn from(err: SecretError) -> Error {
+        Error::Secret(err)
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Error {
         Error::SerdeJson(err)
     }
 }
 
+impl From<SshError> for Error {
+    fn from(err: SshError) -> Error {
+        Error::Ssh(err)
+    }
+}
+
 impl From<ssh2::Error> for Error {
     fn from(err: ssh2::Error) -> Error {
         Error::Ssh2(err)
@@ -172,6 +254,12 @@ impl From<string::FromUtf8Error> for Error {
     }
 }
 
+impl From<TemplateError> for Error {
+    fn from(err: TemplateError) -> Error {
+        Error::Template(err)
+    }
+}
+
 impl From<zdaemon::Error> for Error {
     fn from(err: zdaemon::Error) -> Error {
         Error::ZDaemon(err)
@@ -0,0 +1,149 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use error::Result;
+use serde_json;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Gathers facts about the host as "key=value" lines.
+pub const FACTS_SOURCE: &'static str = "echo \"os=$(uname -s)\"
+echo \"arch=$(uname -m)\"
+if [ -f /etc/os-release ]; then
+    . /etc/os-release
+    echo \"platform=${ID:-}\"
+    echo \"version=${VERSION_ID:-}\"
+else
+    echo \"version=$(uname -r)\"
+fi
+for _ip in $(hostname -I 2>/dev/null || ifconfig 2>/dev/null | awk \"/inet6? / { print \\$2 }\" | sed s/addr://); do
+    case $_ip in
+        127.*|::1|fe80:*) ;;
+        *) echo \"ip=$_ip\" ;;
+    esac
+done
+";
+
+// Default agent ports
+const API_PORT: u32 = 7101;
+const FILE_PORT: u32 = 7102;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Facts {
+    pub os: String,
+    pub platform: Option<String>,
+    pub version: Option<String>,
+    pub arch: String,
+    pub ips: Vec<String>,
+}
+
+impl Facts {
+    /// Parse the output of `FACTS_SOURCE`.
+    pub fn parse(output: &str) -> Facts {
+        let mut facts = Facts::default();
+
+        for line in output.lines() {
+            let mut parts = line.trim().splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(k), Some(v)) if !v.is_empty() => (k, v.to_owned()),
+                _ => continue,
+            };
+
+            match key {
+                "os" => facts.os = value.to_lowercase(),
+                "arch" => facts.arch = value,
+                "platform" => facts.platform = Some(value),
+                "version" => facts.version = Some(value),
+                "ip" => if !facts.ips.contains(&value) {
+                    facts.ips.push(value)
+                },
+                _ => (),
+            }
+        }
+
+        facts
+    }
+}
+
+/// Path to a host's data file in the project.
+pub fn path<P: AsRef<Path>>(project_path: P, hostname: &str) -> PathBuf {
+    let mut buf = project_path.as_ref().to_owned();
+    buf.push("data/hosts");
+    buf.push(format!("{}.json", hostname));
+    buf
+}
+
+/// Write a new host data file with no payloads.
+pub fn create(path: &Path, hostname: &str, facts: &Facts) -> Result<()> {
+    let data = json!({
+        "hostname": hostname,
+        "api_port": API_PORT,
+        "file_port": FILE_PORT,
+        "facts": {
+            "os": facts.os,
+            "platform": facts.platform,
+            "version": facts.version,
+            "arch": facts.arch,
+            "ips": facts.ips,
+        },
+        "_payloads": [],
+    });
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut fh = fs::File::create(path)?;
+    fh.write_all(serde_json::to_string_pretty(&data)?.as_bytes())?;
+    fh.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{self, Value};
+    use std::fs::File;
+    use std::io::Read;
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_parse() {
+        let facts = Facts::parse("os=Linux
+arch=x86_64
+platform=ubuntu
+version=16.04
+ip=10.0.0.5
+ip=fd00::5
+ip=10.0.0.5
+");
+        assert_eq!(facts, Facts {
+            os: "linux".into(),
+            platform: Some("ubuntu".into()),
+            version: Some("16.04".into()),
+            arch: "x86_64".into(),
+            ips: vec!["10.0.0.5".into(), "fd00::5".into()],
+        });
+    }
+
+    #[test]
+    fn test_create() {
+        let dir = TempDir::new("test_hostdata_create").unwrap();
+        let path = path(dir.path(), "web1");
+        create(&path, "web1", &Facts::parse("os=FreeBSD\narch=amd64\n")).unwrap();
+
+        let mut fh = File::open(&path).unwrap();
+        let mut json = String::new();
+        fh.read_to_string(&mut json).unwrap();
+        let data: Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(data.get("hostname").and_then(|h| h.as_str()), Some("web1"));
+        assert_eq!(data.get("facts").and_then(|f| f.get("os")).and_then(|o| o.as_str()), Some("freebsd"));
+        assert_eq!(data.get("_payloads").and_then(|p| p.as_array()).map(|p| p.len()), Some(0));
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use error::Result;
+use rustc_serialize::base64::{STANDARD, ToBase64};
+use ssh2::{CheckResult, HashType, HostKeyType, KnownHostFileKind, Session};
+use std::{env, error, fmt, fs};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const PROJECT_KNOWN_HOSTS: &'static str = "known_hosts";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HostKeyCheck {
+    /// Host must already be present in known_hosts.
+    Strict,
+    /// Unknown hosts are added to known_hosts; changed keys are
+    /// rejected.
+    AcceptNew,
+    /// Host key must match this fingerprint (hex encoded SHA1 or MD5).
+    Fingerprint(String),
+}
+
+impl HostKeyCheck {
+    pub fn new(mode: &str, fingerprint: Option<&str>) -> Result<HostKeyCheck> {
+        if let Some(fp) = fingerprint {
+            return HostKeyCheck::fingerprint(fp);
+        }
+
+        match mode {
+            "strict" => Ok(HostKeyCheck::Strict),
+            "accept-new" => Ok(HostKeyCheck::AcceptNew),
+            _ => Err(HostKeyError::InvalidMode(mode.into()).into()),
+        }
+    }
+
+    /// Only accept a host key with this SHA1 or MD5 fingerprint.
+    pub fn fingerprint(fp: &str) -> Result<HostKeyCheck> {
+        match normalise_fingerprint(fp) {
+            Some(fp) => Ok(HostKeyCheck::Fingerprint(fp)),
+            None => Err(HostKeyError::InvalidFingerprint(fp.into()).into()),
+        }
+    }
+}
+
+/// Verify the host key presented by `session`. This must be called
+/// after the handshake and before authenticating.
+pub fn verify(session: &Session, hostname: &str, port: u16, check: &HostKeyCheck) -> Result<()> {
+    let (key, key_type) = session.host_key().ok_or(HostKeyError::NoKey)?;
+
+    if let HostKeyCheck::Fingerprint(ref expected) = *check {
+        let hash_type = if expected.len() == 40 { HashType::Sha1 } else { HashType::Md5 };
+        let actual = fingerprint_hex(session.host_key_hash(hash_type).ok_or(HostKeyError::NoKey)?);
+        return if &actual == expected {
+            Ok(())
+        } else {
+            Err(HostKeyError::FingerprintMismatch(hostname.into(), expected.clone(), actual).into())
+        };
+    }
+
+    let path = known_hosts_path().ok_or(HostKeyError::NoKnownHosts)?;
+    let mut known_hosts = session.known_hosts()?;
+    if path.exists() {
+        known_hosts.read_file(&path, KnownHostFileKind::OpenSSH)?;
+    }
+
+    match known_hosts.check_port(hostname, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(HostKeyError::Mismatch(hostname.into(), path).into()),
+        CheckResult::Failure => Err(HostKeyError::Failure.into()),
+        CheckResult::NotFound if *check == HostKeyCheck::Strict => Err(HostKeyError::NotFound(hostname.into(), path).into()),
+        CheckResult::NotFound => {
+            let key_name = match key_type {
+                HostKeyType::Rsa => "ssh-rsa",
+                HostKeyType::Dss => "ssh-dss",
+                HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+                HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+                HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+                HostKeyType::Ed25519 => "ssh-ed25519",
+                HostKeyType::Unknown => return Err(HostKeyError::UnknownKeyType.into()),
+            };
+
+            // OpenSSH only qualifies the host with a port when it
+            // isn't the default.
+            let name = if port == 22 { hostname.to_owned() } else { format!("[{}]:{}", hostname, port) };
+
+            // Append rather than rewriting the whole file, so that
+            // hosts bootstrapped in parallel don't clobber each other.
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut fh = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+            fh.write_all(format!("{} {} {}\n", name, key_name, key.to_base64(STANDARD)).as_bytes())?;
+
+            println!("Warning: Permanently added '{}' to the list of known hosts in {}.", name, path.display());
+            Ok(())
+        },
+    }
+}
+
+/// Prefer a known_hosts file in the project root, falling back to
+/// the user's ~/.ssh/known_hosts.
+fn known_hosts_path() -> Option<PathBuf> {
+    let project = Path::new(PROJECT_KNOWN_HOSTS);
+    if project.exists() {
+        return Some(project.to_owned());
+    }
+
+    env::var("HOME").ok().map(|home| {
+        let mut buf = PathBuf::from(home);
+        buf.push(".ssh/known_hosts");
+        buf
+    })
+}
+
+fn fingerprint_hex(hash: &[u8]) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Accepts fingerprints in the forms "SHA1:aa:bb:..", "aa:bb:.." or
+/// "aabb..", returning lowercase hex without separators.
+fn normalise_fingerprint(fp: &str) -> Option<String> {
+    let lower = fp.trim().to_lowercase();
+    let hex: String = lower.trim_left_matches("sha1:")
+                           .trim_left_matches("md5:")
+                           .chars()
+                           .filter(|c| *c != ':')
+                           .collect();
+
+    if (hex.len() == 40 || hex.len() == 32) && hex.chars().all(|c| c.is_digit(16)) {
+        Some(hex)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum HostKeyError {
+    Failure,
+    FingerprintMismatch(String, String, String),
+    InvalidFingerprint(String),
+    InvalidMode(String),
+    Mismatch(String, PathBuf),
+    NoKey,
+    NoKnownHosts,
+    NotFound(String, PathBuf),
+    UnknownKeyType,
+}
+
+impl fmt::Display for HostKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HostKeyError::Failure => write!(f, "Could not check host key against known_hosts"),
+            HostKeyError::FingerprintMismatch(ref h, ref e, ref a) => write!(f, "Host key fingerprint for {} does not match (expected {}, got {})", h, e, a),
+            HostKeyError::InvalidFingerprint(ref fp) => write!(f, "Invalid host key fingerprint: {}", fp),
+            HostKeyError::InvalidMode(ref m) => write!(f, "Unknown host key check mode: {}", m),
+            HostKeyError::Mismatch(ref h, ref p) => write!(f, "Host key for {} does not match the key in {}. Someone could be eavesdropping on you (man-in-the-middle attack)!", h, p.display()),
+            HostKeyError::NoKey => write!(f, "Host did not present a host key"),
+            HostKeyError::NoKnownHosts => write!(f, "Could not find a known_hosts file"),
+            HostKeyError::NotFound(ref h, ref p) => write!(f, "Host {} is not in {} and strict host key checking is enabled", h, p.display()),
+            HostKeyError::UnknownKeyType => write!(f, "Host presented a key of unknown type"),
+        }
+    }
+}
+
+impl error::Error for HostKeyError {
+    fn description(&self) -> &str {
+        match *self {
+            HostKeyError::Failure => "Could not check host key against known_hosts",
+            HostKeyError::FingerprintMismatch(..) => "Host key fingerprint does not match",
+            HostKeyError::InvalidFingerprint(_) => "Invalid host key fingerprint",
+            HostKeyError::InvalidMode(_) => "Unknown host key check mode",
+            HostKeyError::Mismatch(..) => "Host key does not match known_hosts",
+            HostKeyError::NoKey => "Host did not present a host key",
+            HostKeyError::NoKnownHosts => "Could not find a known_hosts file",
+            HostKeyError::NotFound(..) => "Host is not in known_hosts",
+            HostKeyError::UnknownKeyType => "Host presented a key of unknown type",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fingerprint_hex, normalise_fingerprint, HostKeyCheck};
+
+    #[test]
+    fn test_new() {
+        assert_eq!(HostKeyCheck::new("strict", None).unwrap(), HostKeyCheck::Strict);
+        assert_eq!(HostKeyCheck::new("accept-new", None).unwrap(), HostKeyCheck::AcceptNew);
+        assert!(HostKeyCheck::new("yolo", None).is_err());
+        assert!(HostKeyCheck::new("strict", Some("nope")).is_err());
+    }
+
+    #[test]
+    fn test_normalise_fingerprint() {
+        let sha1 = "0123456789abcdef0123456789abcdef01234567";
+        assert_eq!(normalise_fingerprint(sha1).unwrap(), sha1);
+        assert_eq!(normalise_fingerprint("SHA1:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:ab:cd:ef:01:23:45:67").unwrap(), sha1);
+        assert_eq!(normalise_fingerprint("MD5:01:23:45:67:89:ab:cd:ef:01:23:45:67:89:ab:cd:ef").unwrap(), "0123456789abcdef0123456789abcdef");
+        assert!(normalise_fingerprint("0123").is_none());
+        assert!(normalise_fingerprint("zz23456789abcdef0123456789abcdef01234567").is_none());
+    }
+
+    #[test]
+    fn test_fingerprint_hex() {
+        assert_eq!(fingerprint_hex(&[0x01, 0xab, 0xff]), "01abff");
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use error::Result;
+use std::{cmp, error, fmt, fs, io, thread};
+use std::io::{BufRead, BufReader};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, mpsc};
+
+pub struct Inventory {
+    hosts: Vec<String>,
+}
+
+pub struct HostResult {
+    pub hostname: String,
+    pub result: ::std::result::Result<(), String>,
+}
+
+impl Inventory {
+    pub fn new(hosts: Vec<String>) -> Inventory {
+        Inventory {
+            hosts: hosts,
+        }
+    }
+
+    /// Load an inventory from a file, or from stdin if `path` is "-".
+    pub fn load(path: &str) -> Result<Inventory> {
+        if path == "-" {
+            let stdin = io::stdin();
+            let lock = stdin.lock();
+            Inventory::parse(lock)
+        } else {
+            let fh = fs::File::open(path)?;
+            Inventory::parse(BufReader::new(fh))
+        }
+    }
+
+    /// Parse an inventory with one hostname per line. Blank lines and
+    /// lines beginning with '#' are ignored.
+    pub fn parse<R: BufRead>(reader: R) -> Result<Inventory> {
+        let mut hosts = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let host = line.trim();
+            if !host.is_empty() && !host.starts_with('#') {
+                hosts.push(host.to_owned());
+            }
+        }
+
+        if hosts.is_empty() {
+            Err(InventoryError::Empty.into())
+        } else {
+            Ok(Inventory {
+                hosts: hosts,
+            })
+        }
+    }
+
+    pub fn hosts(&self) -> &[String] {
+        &self.hosts
+    }
+
+    /// Run `f` against every host in the inventory, with at most
+    /// `concurrency` hosts in flight at once. Results are returned in
+    /// inventory order.
+    pub fn run<F>(&self, concurrency: usize, f: F) -> Vec<HostResult>
+        where F: Fn(&str) -> Result<()> + Send + Sync + 'static
+    {
+        let queue: Vec<(usize, String)> = self.hosts.iter().cloned().enumerate().collect();
+        let queue = Arc::new(Mutex::new(queue.into_iter()));
+        let f = Arc::new(f);
+        let (tx, rx) = mpsc::channel();
+
+        let workers = if concurrency == 0 || concurrency > self.hosts.len() {
+            self.hosts.len()
+        } else {
+            concurrency
+        };
+
+        let mut handles = Vec::new();
+        for _ in 0..workers {
+            let queue = queue.clone();
+            let f = f.clone();
+            let tx = tx.clone();
+
+            handles.push(thread::spawn(move || {
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    let (index, hostname) = match next {
+                        Some(n) => n,
+                        None => break,
+                    };
+
+                    // A panic shouldn't take the rest of this worker's
+                    // hosts down with it.
+                    let result = match panic::catch_unwind(AssertUnwindSafe(|| f(&hostname))) {
+                        Ok(Ok(())) => Ok(()),
+                        Ok(Err(e)) => Err(e.to_string()),
+                        Err(_) => Err("Bootstrap panicked".into()),
+                    };
+
+                    if tx.send((index, HostResult { hostname: hostname, result: result })).is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+        drop(tx);
+
+        let mut results = Vec::new();
+        for (index, result) in rx.iter() {
+            match result.result {
+                Ok(_) => println!("{}: done", result.hostname),
+                Err(_) => println!("{}: error!", result.hostname),
+            }
+            results.push((index, result));
+        }
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+
+        results.sort_by_key(|&(index, _)| index);
+        results.into_iter().map(|(_, r)| r).collect()
+    }
+}
+
+/// Print a per-host summary table, returning an error if any host
+/// failed.
+pub fn summarise(results: &[HostResult]) -> Result<()> {
+    let width = cmp::max(results.iter().map(|r| r.hostname.len()).max().unwrap_or(0), 4);
+
+    println!("");
+    println!("{:2$}  {}", "HOST", "RESULT", width);
+    for r in results {
+        match r.result {
+            Ok(_) => println!("{:2$}  {}", r.hostname, "ok", width),
+            Err(ref e) => println!("{:2$}  failed: {}", r.hostname, e, width),
+        }
+    }
+
+    let failed = results.iter().filter(|r| r.result.is_err()).count();
+    if failed > 0 {
+        Err(InventoryError::Failed(failed, results.len()).into())
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum InventoryError {
+    Empty,
+    Failed(usize, usize),
+}
+
+impl fmt::Display for InventoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InventoryError::Empty => write!(f, "Inventory contains no hosts"),
+            InventoryError::Failed(failed, total) => write!(f, "{} of {} hosts failed", failed, total),
+        }
+    }
+}
+
+impl error::Error for InventoryError {
+    fn description(&self) -> &str {
+        match *self {
+            InventoryError::Empty => "Inventory contains no hosts",
+            InventoryError::Failed(_, _) => "One or more hosts failed",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use std::io::Cursor;
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let inventory = Inventory::parse(Cursor::new("# web servers
+web1.example.com
+  web2.example.com
+
+db1.example.com
+")).unwrap();
+        assert_eq!(inventory.hosts(), &["web1.example.com", "web2.example.com", "db1.example.com"]);
+
+        assert!(Inventory::parse(Cursor::new("\n# nothing here\n")).is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let inventory = Inventory::parse(Cursor::new("a\nb\nc\nd\n")).unwrap();
+        let results = inventory.run(2, |host| {
+            if host == "c" {
+                Err(Error::Bootstrap("nope".into()))
+            } else {
+                Ok(())
+            }
+        });
+
+        let hosts: Vec<&str> = results.iter().map(|r| &*r.hostname).collect();
+        assert_eq!(hosts, vec!["a", "b", "c", "d"]);
+        assert!(results[2].result.is_err());
+        assert!(summarise(&results).is_err());
+    }
+}
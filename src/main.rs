@@ -11,6 +11,7 @@ extern crate docopt;
 extern crate inapi;
 extern crate rustc_serialize;
 extern crate serde;
+#[macro_use]
 extern crate serde_json;
 extern crate ssh2;
 #[cfg(test)]
@@ -22,22 +23,39 @@ mod auth;
 mod bootstrap;
 mod cert;
 mod error;
+mod hostdata;
+mod hostkey;
+mod inventory;
 mod language;
 mod payload;
+mod preflight;
+mod privilege;
 mod project;
+mod secret;
+mod ssh;
+mod sshconfig;
+mod template;
+mod transport;
 
 use auth::Auth;
-use bootstrap::Bootstrap;
+use bootstrap::{Bootstrap, InstallOptions};
 use docopt::Docopt;
 use error::Result;
+use hostkey::HostKeyCheck;
+use inventory::Inventory;
 use language::language_from_str;
 use payload::Payload;
+use privilege::BecomeMethod;
 use project::Project;
 use serde::{Serialize, Deserialize};
+use ssh::{Credentials, Timeouts};
+use sshconfig::HostConfig;
 use std::{env, fs};
 use std::io::{Read, Write, self};
 use std::path::Path;
 use std::process::exit;
+use std::sync::Arc;
+use transport::{DockerTransport, LocalTransport};
 
 const API_VERSION: &'static str = "0.3";
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
@@ -50,56 +68,162 @@ Usage:
   incli project init <name> <lang>
   incli payload init <name> <lang>
   incli payload build [<names>...]
-  incli host (add | delete) [(-s | --silent)] <hostname>
-  incli host bootstrap <hostname> [-u <username>] [-P <password>] [-i <identity_file>] [-p <ssh_port>] [-m <preinstall_script>] [-n <postinstall_script>]
+  incli host add [(-s | --silent)] <hostname> [--meta <key=value>]...
+  incli host delete [(-s | --silent)] <hostname>
+  incli host bootstrap (<hostname> | --inventory <file>) [--concurrency <n>] [-u <username>] [-P <password> | --ask-pass | --pass-env <var> | --pass-fd <fd>] [-i <identity_file>] [-p <ssh_port>] [--become-method <method>] [--sudo-password | --sudo-password-env <var>] [-m <preinstall_script> | --preinstall-file <file>] [-n <postinstall_script> | --postinstall-file <file>] [--agent-bundle <file> | --agent-version <version>] [--reuse-cert] [--host-key-check <mode>] [--host-key-fingerprint <fp>] [--jump <jump_host>]... [--connect-timeout <secs>] [--handshake-timeout <secs>] [--command-timeout <secs>] [--retries <n>] [--upload <local:remote>]... [--var <key=value>]... [--preflight-only]
+  incli host bootstrap <hostname> (--local | --docker <container>) [--become-method <method>] [--sudo-password | --sudo-password-env <var>] [-m <preinstall_script> | --preinstall-file <file>] [-n <postinstall_script> | --postinstall-file <file>] [--agent-bundle <file> | --agent-version <version>] [--reuse-cert] [--upload <local:remote>]... [--var <key=value>]... [--preflight-only]
+  incli host bootstrap <hostname> --dry-run [-o <script_file>] [-u <username>] [--become-method <method>] [--sudo-password] [-m <preinstall_script> | --preinstall-file <file>] [-n <postinstall_script> | --postinstall-file <file>] [--agent-bundle <file> | --agent-version <version>] [--var <key=value>]...
+  incli host bootstrap --list-vars
+  incli host decommission [(-s | --silent)] <hostname> [-u <username>] [-P <password> | --ask-pass | --pass-env <var> | --pass-fd <fd>] [-i <identity_file>] [-p <ssh_port>] [--become-method <method>] [--sudo-password | --sudo-password-env <var>] [--host-key-check <mode>] [--host-key-fingerprint <fp>] [--jump <jump_host>]... [--connect-timeout <secs>] [--handshake-timeout <secs>] [--command-timeout <secs>] [--retries <n>]
+  incli host rekey <hostname> [-u <username>] [-P <password> | --ask-pass | --pass-env <var> | --pass-fd <fd>] [-i <identity_file>] [-p <ssh_port>] [--become-method <method>] [--sudo-password | --sudo-password-env <var>] [--reuse-cert] [--host-key-check <mode>] [--host-key-fingerprint <fp>] [--jump <jump_host>]... [--connect-timeout <secs>] [--handshake-timeout <secs>] [--command-timeout <secs>] [--retries <n>]
+  incli host upgrade-agent (<hostnames>... | --inventory <file>) [--concurrency <n>] [-u <username>] [-P <password> | --ask-pass | --pass-env <var> | --pass-fd <fd>] [-i <identity_file>] [-p <ssh_port>] [--become-method <method>] [--sudo-password | --sudo-password-env <var>] [--agent-bundle <file> | --agent-version <version>] [--host-key-check <mode>] [--host-key-fingerprint <fp>] [--jump <jump_host>]... [--connect-timeout <secs>] [--handshake-timeout <secs>] [--command-timeout <secs>] [--retries <n>]
   incli host list
-  incli user (add | delete) [(-s | --silent)] <username>
+  incli host show <hostname>
+  incli user add [(-s | --silent)] <username> [--meta <key=value>]...
+  incli user delete [(-s | --silent)] <username>
   incli user list
+  incli user show <username>
   incli (-h | --help)
   incli --version
 
 Options:
+  --agent-bundle <file>     Upload and install this Agent package instead of
+                            downloading it from get.intecture.io.
+  --agent-version <version>  Install this Agent version from
+                            get.intecture.io instead of the latest.
+                            Overrides \"agent_version\" in project.json.
+  --ask-pass                Prompt for the SSH password or key passphrase
+                            before connecting.
+  --become-method <method>  How to become root when not connecting as root.
+                            One of \"sudo\", \"doas\" or \"su\" [default: sudo].
+                            doas and su must not need a password.
+  --command-timeout <secs>  Give up on a remote command after this many
+                            seconds, or 0 to wait forever [default: 0].
+  --concurrency <n>         Max hosts to work on at once [default: 10].
+  --connect-timeout <secs>  Give up connecting to a host after this many
+                            seconds [default: 10].
+  --docker <container>      Bootstrap a running Docker container instead of
+                            connecting over SSH.
+  --dry-run                 Print the bootstrap script instead of running it.
   -h --help                 Show this screen.
+  --handshake-timeout <secs>  Give up on SSH negotiation and authentication
+                            after this many seconds [default: 30].
+  --host-key-check <mode>   How to verify SSH host keys against known_hosts.
+                            One of \"strict\" or \"accept-new\"
+                            [default: accept-new].
+  --host-key-fingerprint <fp>  Only accept a host key with this SHA1 or MD5
+                            fingerprint. Jump hosts need their own
+                            \"fingerprint\" in project.json.
   -i <identity_file>        Path to SSH private key.
-  --local                   Ignore build server and run project locally.
-  -m <preinstall_script>    Script to run before attempting to install Agent.
-  -n <postinstall_script>   Script to run after successfully installing Agent.
+  --inventory <file>        File listing hosts to work on, one per line.
+                            Use \"-\" to read from stdin.
+  --jump <jump_host>        Tunnel through an SSH jump host, given as
+                            [user@]host[:port]. Repeat or comma-separate
+                            for multiple hops. Entries in project.json's
+                            \"jump_hosts\" can set \"password_env\" and
+                            \"fingerprint\".
+  --list-vars               List the variables built into bootstrap
+                            templates.
+  --local                   Ignore build server and run project locally. When
+                            bootstrapping, install Agent on this machine.
+  -m <preinstall_script>    Shell to run before attempting to install Agent.
+  --meta <key=value>        Attach metadata to a new certificate. Repeat
+                            for multiple keys.
+  -n <postinstall_script>   Shell to run after successfully installing Agent.
+  -o <script_file>          Save the rendered script to a file.
   -p <ssh_port>             SSH port number.
-  -P <password>             SSH password.
+  -P <password>             SSH password, or passphrase if -i is given.
+  --pass-env <var>          Read the SSH password or key passphrase from
+                            this environment variable.
+  --pass-fd <fd>            Read the SSH password or key passphrase from
+                            this file descriptor.
+  --postinstall-file <file>  Upload this script and run it after successfully
+                            installing Agent.
+  --preflight-only          Check that hosts are ready to bootstrap without
+                            installing anything.
+  --preinstall-file <file>  Upload this script and run it before attempting
+                            to install Agent.
+  --retries <n>             Times to retry connecting to a host that is
+                            unreachable or drops the connection
+                            [default: 3].
+  --reuse-cert              Install the host certificate saved in
+                            <hostname>.crt instead of creating a new one,
+                            e.g. to retry a failed bootstrap or rekey.
   -s --silent               Save private key instead of printing it.
+  --sudo-password           Prompt for the password used to become root.
+  --sudo-password-env <var>  Read the password used to become root from this
+                            environment variable.
   -u <username>             SSH username.
+  --upload <local:remote>   Copy a file to the host before installing Agent.
+                            Repeat for multiple files.
+  --var <key=value>         Set a variable for a custom bootstrap template,
+                            referenced in the template as {{key}}. See
+                            --list-vars for the built-in variables.
   -v --verbose              Verbose output.
   --version                 Print this script's version.
 ";
 
-#[derive(Debug, RustcDecodable)]
+#[derive(Clone, Debug, RustcDecodable)]
 #[allow(non_snake_case)]
 struct Args {
     cmd_add: bool,
     cmd_bootstrap: bool,
     cmd_build: bool,
+    cmd_decommission: bool,
     cmd_delete: bool,
     cmd_host: bool,
     cmd_init: bool,
     cmd_list: bool,
     cmd_payload: bool,
     cmd_project: bool,
+    cmd_rekey: bool,
     cmd_run: bool,
+    cmd_show: bool,
+    cmd_upgrade_agent: bool,
     cmd_user: bool,
+    flag_agent_bundle: Option<String>,
+    flag_agent_version: Option<String>,
+    flag_ask_pass: bool,
+    flag_become_method: String,
+    flag_command_timeout: u64,
+    flag_concurrency: usize,
+    flag_connect_timeout: u64,
+    flag_docker: Option<String>,
+    flag_dry_run: bool,
     flag_h: bool,
+    flag_handshake_timeout: u64,
     flag_help: bool,
+    flag_host_key_check: String,
+    flag_host_key_fingerprint: Option<String>,
     flag_i: Option<String>,
+    flag_inventory: Option<String>,
+    flag_jump: Vec<String>,
+    flag_list_vars: bool,
     flag_local: bool,
     flag_m: Option<String>,
+    flag_meta: Vec<String>,
     flag_n: Option<String>,
+    flag_o: Option<String>,
     flag_p: Option<u32>,
     flag_P: Option<String>,
+    flag_pass_env: Option<String>,
+    flag_pass_fd: Option<i32>,
+    flag_postinstall_file: Option<String>,
+    flag_preflight_only: bool,
+    flag_preinstall_file: Option<String>,
+    flag_retries: u32,
+    flag_reuse_cert: bool,
     flag_s: bool,
     flag_silent: bool,
+    flag_sudo_password: bool,
+    flag_sudo_password_env: Option<String>,
     flag_version: bool,
     flag_u: Option<String>,
+    flag_upload: Vec<String>,
+    flag_var: Vec<String>,
     arg_arg: Vec<String>,
     arg_hostname: String,
+    arg_hostnames: Vec<String>,
     arg_lang: String,
     arg_name: String,
     arg_names: Option<Vec<String>>,
@@ -150,6 +274,23 @@ fn run(args: &Args) -> Result<()> {
             }
         }
     }
+    else if args.cmd_host && args.flag_list_vars {
+        println!("Built-in template variables:\n{}", template::builtin_vars_help());
+    }
+    else if args.cmd_bootstrap && args.cmd_host && args.flag_dry_run {
+        // A dry run connects to nothing, so it mustn't need user.crt
+        // or the auth server either
+        let opts = try!(install_options(args));
+        let (hostname, username) = try!(dry_run_target(args));
+        let script = Bootstrap::dry_run(&hostname, username.as_ref().map(|u| &**u), &opts)?;
+        match args.flag_o {
+            Some(ref path) => {
+                let mut fh = try!(fs::File::create(path));
+                try!(fh.write_all(script.as_bytes()));
+            },
+            None => println!("{}", script),
+        }
+    }
     else if args.cmd_host || args.cmd_user {
         let cert_type = if args.cmd_host { "host" } else { "user" };
         let name = if args.cmd_host { &args.arg_hostname } else { &args.arg_username };
@@ -157,7 +298,12 @@ fn run(args: &Args) -> Result<()> {
         let mut auth = try!(Auth::new(&env::current_dir().unwrap()));
 
         if args.cmd_add {
-            let cert = try!(auth.add(cert_type, name));
+            let mut meta = Vec::new();
+            for m in &args.flag_meta {
+                meta.push(try!(cert::parse_meta(m)));
+            }
+
+            let cert = try!(auth.add(cert_type, name, &meta));
             if args.flag_s || args.flag_silent {
                 try!(cert.save_secret(&format!("{}.crt", name)));
             } else {
@@ -169,30 +315,66 @@ fn run(args: &Args) -> Result<()> {
             }
         }
         else if args.cmd_delete {
-            if args.flag_s || args.flag_silent {
+            if args.flag_s || args.flag_silent || confirm("Are you sure you want to delete this certificate?") {
                 try!(auth.delete(name));
-            } else {
-                println!("Are you sure you want to delete this certificate?");
-                loop {
-                    println!("Please enter [y/n]: ");
-                    let mut input = String::new();
-                    match io::stdin().read_line(&mut input) {
-                        Ok(_) => match { input.as_ref() as &str }.trim() {
-                            "y" => {
-                                try!(auth.delete(name));
-                                break;
-                            },
-                            "n" => break,
-                            _ => (),
-                        },
-                        Err(e) => {
-                            println!("Stdin error: {}", e);
-                            exit(1);
-                        },
+            }
+        }
+        else if args.cmd_decommission {
+            if args.flag_s || args.flag_silent || confirm(&format!("Are you sure you want to uninstall Agent from {} and delete its certificate?", name)) {
+                let mut opts = try!(install_options(args));
+                let creds = try!(credentials(args, secret::is_interactive()));
+                print!("Connecting to {}...", name);
+                let mut bootstrap = try!(connect(args, &creds, name));
+                println!("done");
+                try!(ask_become_password(&mut bootstrap, &mut opts));
+
+                println!("Decommissioning...");
+                match bootstrap.decommission(&opts) {
+                    Ok(()) => println!("done"),
+                    Err(e) => {
+                        println!("error!");
+                        return Err(e);
                     }
                 }
             }
         }
+        else if args.cmd_rekey {
+            let mut opts = try!(install_options(args));
+            let creds = try!(credentials(args, secret::is_interactive()));
+            print!("Connecting to {}...", name);
+            let mut bootstrap = try!(connect(args, &creds, name));
+            println!("done");
+            try!(ask_become_password(&mut bootstrap, &mut opts));
+
+            println!("Rotating certificate...");
+            match bootstrap.rekey(&opts) {
+                Ok(()) => println!("done"),
+                Err(e) => {
+                    println!("error!");
+                    return Err(e);
+                }
+            }
+        }
+        else if args.cmd_upgrade_agent {
+            let opts = try!(install_options(args));
+            let inventory = match args.flag_inventory {
+                Some(ref path) => try!(Inventory::load(path)),
+                None => Inventory::new(args.arg_hostnames.clone()),
+            };
+            let shared_args = Arc::new(args.clone());
+            let creds = try!(credentials(args, false));
+
+            println!("Upgrading Agent on {} hosts...", inventory.hosts().len());
+            let results = inventory.run(args.flag_concurrency, move |hostname| {
+                let mut bootstrap = connect(&shared_args, &creds, hostname)?;
+                bootstrap.upgrade_agent(&opts)
+            });
+            try!(inventory::summarise(&results));
+        }
+        else if args.cmd_show {
+            let info = try!(auth.lookup(cert_type, name));
+            println!("{}", info.details());
+        }
         else if args.cmd_list {
             let names = try!(auth.list(cert_type));
 
@@ -205,20 +387,41 @@ fn run(args: &Args) -> Result<()> {
             }
         }
         else if args.cmd_bootstrap && args.cmd_host {
-            print!("Connecting to {}...", args.arg_hostname);
-            let mut bootstrap = Bootstrap::new(&args.arg_hostname,
-                                               args.flag_p,
-                                               args.flag_u.as_ref().map(|u| &**u),
-                                               args.flag_P.as_ref().map(|p| &**p),
-                                               args.flag_i.as_ref().map(|i| &**i))?;
-            println!("done");
+            let mut opts = try!(install_options(args));
 
-            print!("Bootstrapping...");
-            match bootstrap.run(args.flag_m.as_ref().map(|m| &**m), args.flag_n.as_ref().map(|n| &**n)) {
-                Ok(()) => println!("done"),
-                Err(e) => {
-                    println!("error!");
-                    return Err(e);
+            if let Some(ref path) = args.flag_inventory {
+                let inventory = try!(Inventory::load(path));
+                let shared_args = Arc::new(args.clone());
+                let shared_opts = opts.clone();
+
+                // Workers can't share the terminal, so any secret has to
+                // be gathered before they start.
+                let creds = try!(credentials(args, false));
+
+                println!("Bootstrapping {} hosts...", inventory.hosts().len());
+                let results = inventory.run(args.flag_concurrency, move |hostname| {
+                    bootstrap_host(&shared_args, &creds, &shared_opts, hostname)
+                });
+                try!(inventory::summarise(&results));
+            } else {
+                let creds = try!(credentials(args, secret::is_interactive()));
+                print!("Connecting to {}...", args.arg_hostname);
+                let mut bootstrap = connect(args, &creds, &args.arg_hostname)?;
+                println!("done");
+                try!(ask_become_password(&mut bootstrap, &mut opts));
+
+                if args.flag_preflight_only {
+                    try!(bootstrap.preflight(&opts));
+                    return Ok(());
+                }
+
+                println!("Bootstrapping...");
+                match bootstrap.run(&opts) {
+                    Ok(()) => println!("done"),
+                    Err(e) => {
+                        println!("error!");
+                        return Err(e);
+                    }
                 }
             }
         }
@@ -227,6 +430,181 @@ fn run(args: &Args) -> Result<()> {
     Ok(())
 }
 
+fn bootstrap_host(args: &Args, creds: &Credentials, opts: &InstallOptions, hostname: &str) -> Result<()> {
+    let mut bootstrap = connect(args, creds, hostname)?;
+    if args.flag_preflight_only {
+        bootstrap.preflight(opts).map(|_| ())
+    } else {
+        bootstrap.run(opts)
+    }
+}
+
+fn connect(args: &Args, creds: &Credentials, hostname: &str) -> Result<Bootstrap> {
+    if args.flag_local {
+        return Bootstrap::with_transport(hostname, Box::new(LocalTransport::new()));
+    }
+    if let Some(ref container) = args.flag_docker {
+        return Bootstrap::with_transport(hostname, Box::new(DockerTransport::new(container)));
+    }
+
+    // Command line flags take precedence over ~/.ssh/config
+    let host_conf = HostConfig::load(hostname)?;
+    let mut creds = creds.clone();
+    if creds.username.is_none() {
+        creds.username = host_conf.user.clone();
+    }
+    // A secret without -i is a password, which mustn't be mistaken for
+    // the passphrase of the host's IdentityFile
+    if creds.identity_file.is_none() && creds.secret.is_none() {
+        creds.identity_file = host_conf.identity_file.clone();
+    }
+    let port = args.flag_p.or(host_conf.port.map(|p| p as u32));
+
+    // A host's ProxyJump is more specific than the project's jump hosts
+    let jump_specs = match host_conf.proxy_jump {
+        Some(ref jump) if args.flag_jump.is_empty() => vec![jump.clone()],
+        _ => args.flag_jump.clone(),
+    };
+    let mut jump_hosts = ssh::jump_hosts(&env::current_dir().unwrap(), &jump_specs)?;
+    for jump in &mut jump_hosts {
+        HostConfig::load(&jump.host)?.apply_to_jump(jump);
+    }
+
+    let host_key_check = HostKeyCheck::new(&args.flag_host_key_check,
+                                           args.flag_host_key_fingerprint.as_ref().map(|f| &**f))?;
+    let timeouts = Timeouts::new(args.flag_connect_timeout,
+                                 args.flag_handshake_timeout,
+                                 args.flag_command_timeout,
+                                 args.flag_retries);
+
+    // The host's cert and data file must use the name the API can
+    // reach it by, so an alias is bootstrapped as its real HostName
+    let hostname = host_conf.hostname.as_ref().map(|h| &**h).unwrap_or(hostname);
+    Bootstrap::new(hostname, port, &creds, &host_key_check, &jump_hosts, &timeouts)
+}
+
+/// Resolve the hostname and user a real run of `args` would bootstrap,
+/// so that a dry run renders the same script.
+fn dry_run_target(args: &Args) -> Result<(String, Option<String>)> {
+    if args.flag_local || args.flag_docker.is_some() {
+        return Ok((args.arg_hostname.clone(), args.flag_u.clone()));
+    }
+
+    // Mirrors connect(): flags take precedence over ~/.ssh/config
+    let host_conf = HostConfig::load(&args.arg_hostname)?;
+    let hostname = host_conf.hostname.clone().unwrap_or_else(|| args.arg_hostname.clone());
+    Ok((hostname, args.flag_u.clone().or(host_conf.user)))
+}
+
+/// Gather SSH credentials from the command line. If `interactive` is
+/// set, missing passwords and passphrases are prompted for as needed.
+fn credentials(args: &Args, interactive: bool) -> Result<Credentials> {
+    let secret = if let Some(ref p) = args.flag_P {
+        Some(p.clone())
+    }
+    else if let Some(ref var) = args.flag_pass_env {
+        Some(try!(secret::from_env(var)))
+    }
+    else if let Some(fd) = args.flag_pass_fd {
+        Some(try!(secret::from_fd(fd)))
+    }
+    else if args.flag_ask_pass {
+        let msg = if args.flag_i.is_some() { "SSH key passphrase: " } else { "SSH password: " };
+        Some(try!(secret::prompt(msg)))
+    } else {
+        None
+    };
+
+    Ok(Credentials {
+        username: args.flag_u.clone(),
+        secret: secret,
+        identity_file: args.flag_i.clone(),
+        interactive: interactive,
+    })
+}
+
+/// Prompt for the become password if the host needs one and we don't
+/// already have it. doas can't be given a password, so isn't prompted for.
+fn ask_become_password(bootstrap: &mut Bootstrap, opts: &mut InstallOptions) -> Result<()> {
+    if opts.become_password.is_none() &&
+       opts.become_method == BecomeMethod::Sudo &&
+       secret::is_interactive() &&
+       try!(bootstrap.needs_become_password(opts.become_method)) {
+        let msg = format!("[{}] password to become root: ", opts.become_method.name());
+        opts.become_password = Some(try!(secret::prompt(&msg)));
+    }
+    Ok(())
+}
+
+/// Ask a yes/no question on stdin.
+fn confirm(question: &str) -> bool {
+    println!("{}", question);
+    loop {
+        println!("Please enter [y/n]: ");
+        let mut input = String::new();
+        match io::stdin().read_line(&mut input) {
+            Ok(_) => match input.trim() {
+                "y" => return true,
+                "n" => return false,
+                _ => (),
+            },
+            Err(e) => {
+                println!("Stdin error: {}", e);
+                exit(1);
+            },
+        }
+    }
+}
+
+fn install_options(args: &Args) -> Result<InstallOptions> {
+    let mut vars = Vec::new();
+    for var in &args.flag_var {
+        vars.push(try!(template::parse_var(var)));
+    }
+
+    let mut uploads = Vec::new();
+    for upload in &args.flag_upload {
+        uploads.push(try!(bootstrap::parse_upload(upload)));
+    }
+
+    let agent_version = try!(bootstrap::agent_version(&env::current_dir().unwrap(),
+                                                      args.flag_agent_version.as_ref().map(|v| &**v)));
+    if let Some(ref version) = agent_version {
+        if !bootstrap::is_api_compatible(version, API_VERSION) {
+            println!("Warning: Agent {} does not implement API version {}. Hosts running it may not work with this project.", version, API_VERSION);
+        }
+    }
+
+    Ok(InstallOptions {
+        preinstall: args.flag_m.clone(),
+        preinstall_file: match args.flag_preinstall_file {
+            Some(ref path) => Some(try!(bootstrap::hook_file(path))),
+            None => None,
+        },
+        postinstall: args.flag_n.clone(),
+        postinstall_file: match args.flag_postinstall_file {
+            Some(ref path) => Some(try!(bootstrap::hook_file(path))),
+            None => None,
+        },
+        agent_bundle: args.flag_agent_bundle.clone(),
+        agent_version: agent_version,
+        reuse_cert: args.flag_reuse_cert,
+        vars: vars,
+        uploads: uploads,
+        become_method: try!(BecomeMethod::new(&args.flag_become_method)),
+        become_password: if let Some(ref var) = args.flag_sudo_password_env {
+            Some(try!(secret::from_env(var)))
+        } else if args.flag_sudo_password && args.flag_dry_run {
+            // Dry runs only show a placeholder
+            Some(String::new())
+        } else if args.flag_sudo_password {
+            Some(try!(secret::prompt("Password to become root: ")))
+        } else {
+            None
+        },
+    })
+}
+
 fn read_conf<P: AsRef<Path>, T: Deserialize>(path: P) -> Result<T> {
     let mut fh = fs::File::open(path.as_ref())?;
     let mut json = String::new();
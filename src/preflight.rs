@@ -0,0 +1,189 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use privilege::Escalation;
+use std::{error, fmt};
+
+// Probes the host and prints one "key=value" per line.
+pub const PREFLIGHT_SOURCE: &'static str = "_tmp=\"${TMPDIR:-/tmp}\"
+echo \"os=$(uname -s)\"
+echo \"arch=$(uname -m)\"
+echo \"uid=$(id -u)\"
+for _cmd in curl pgrep mktemp tar; do
+    if command -v $_cmd >/dev/null 2>&1; then echo \"cmd.$_cmd=yes\"; else echo \"cmd.$_cmd=no\"; fi
+done
+echo \"disk_free_kb=$(df -Pk \"$_tmp\" 2>/dev/null | tail -n 1 | awk \"{ print \\$4 }\")\"
+if command -v inagent >/dev/null 2>&1 || [ -x /usr/local/bin/inagent ]; then echo inagent=yes; else echo inagent=no; fi
+if pgrep -x inagent >/dev/null 2>&1; then echo inagent_running=yes; else echo inagent_running=no; fi
+";
+
+/// Minimum free space in the temp dir needed to download and unpack
+/// the agent.
+const MIN_FREE_KB: u64 = 50 * 1024;
+
+/// What we learnt about a host before installing the agent.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub os: String,
+    pub arch: String,
+    pub is_root: bool,
+    /// How we can become root, if not already. This isn't probed by
+    /// `PREFLIGHT_SOURCE` as it depends on the become method.
+    pub escalation: Option<Escalation>,
+    /// Required commands and whether they are present.
+    pub commands: Vec<(String, bool)>,
+    pub disk_free_kb: Option<u64>,
+    pub agent_installed: bool,
+    pub agent_running: bool,
+}
+
+impl Report {
+    /// Parse the output of `PREFLIGHT_SOURCE`.
+    pub fn parse(output: &str) -> Report {
+        let mut report = Report::default();
+
+        for line in output.lines() {
+            let mut parts = line.trim().splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(k), Some(v)) => (k, v),
+                _ => continue,
+            };
+
+            match key {
+                "os" => report.os = value.into(),
+                "arch" => report.arch = value.into(),
+                "uid" => report.is_root = value == "0",
+                "disk_free_kb" => report.disk_free_kb = value.parse().ok(),
+                "inagent" => report.agent_installed = value == "yes",
+                "inagent_running" => report.agent_running = value == "yes",
+                _ if key.starts_with("cmd.") => report.commands.push((key[4..].into(), value == "yes")),
+                _ => (),
+            }
+        }
+
+        report
+    }
+
+    /// Problems that would stop the agent from installing. `offline`
+    /// is set if the agent is uploaded rather than downloaded.
+    pub fn problems(&self, offline: bool) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if !self.is_root && self.escalation.is_none() {
+            problems.push("cannot become root without a password".into());
+        }
+
+        let needed = if offline { ["tar", "pgrep", "mktemp"] } else { ["curl", "pgrep", "mktemp"] };
+        for cmd in needed.iter() {
+            if !self.has_command(cmd) {
+                problems.push(format!("{} is not installed", cmd));
+            }
+        }
+
+        match self.disk_free_kb {
+            Some(kb) if kb < MIN_FREE_KB => problems.push(format!("only {} MB free in temp dir, need {} MB", kb / 1024, MIN_FREE_KB / 1024)),
+            Some(_) => (),
+            None => problems.push("could not determine free space in temp dir".into()),
+        }
+
+        problems
+    }
+
+    fn has_command(&self, cmd: &str) -> bool {
+        self.commands.iter().any(|&(ref c, present)| c == cmd && present)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let privileges = match self.escalation {
+            _ if self.is_root => "root".into(),
+            Some(ref e) => e.to_string(),
+            None => "cannot become root".into(),
+        };
+        let commands: Vec<String> = self.commands.iter()
+                                                 .map(|&(ref c, present)| format!("{} {}", c, if present { "ok" } else { "missing" }))
+                                                 .collect();
+        let disk = match self.disk_free_kb {
+            Some(kb) => format!("{} MB free", kb / 1024),
+            None => "unknown".into(),
+        };
+
+        writeln!(f, "  OS:          {} {}", self.os, self.arch)?;
+        writeln!(f, "  Privileges:  {}", privileges)?;
+        writeln!(f, "  Commands:    {}", commands.join(", "))?;
+        writeln!(f, "  Temp space:  {}", disk)?;
+        let agent = match (self.agent_installed, self.agent_running) {
+            (true, true) => "installed and running",
+            (true, false) => "installed, not running",
+            (false, _) => "not installed",
+        };
+        write!(f, "  Agent:       {}", agent)
+    }
+}
+
+#[derive(Debug)]
+pub enum PreflightError {
+    Failed(String, Vec<String>),
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PreflightError::Failed(ref h, ref p) => write!(f, "Preflight checks failed for {}: {}", h, p.join("; ")),
+        }
+    }
+}
+
+impl error::Error for PreflightError {
+    fn description(&self) -> &str {
+        match *self {
+            PreflightError::Failed(..) => "Preflight checks failed",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let report = Report::parse("os=Linux
+arch=x86_64
+uid=1000
+cmd.curl=yes
+cmd.pgrep=no
+cmd.mktemp=yes
+cmd.tar=yes
+disk_free_kb=1048576
+inagent=yes
+inagent_running=no
+");
+        assert_eq!(report.os, "Linux");
+        assert_eq!(report.arch, "x86_64");
+        assert!(!report.is_root);
+        assert!(report.escalation.is_none());
+        assert_eq!(report.commands.len(), 4);
+        assert_eq!(report.disk_free_kb, Some(1048576));
+        assert!(report.agent_installed);
+        assert!(!report.agent_running);
+        assert_eq!(report.problems(false), vec!["cannot become root without a password".to_owned(), "pgrep is not installed".to_owned()]);
+    }
+
+    #[test]
+    fn test_problems() {
+        let report = Report::parse("uid=1000\ncmd.curl=no\ncmd.pgrep=yes\ncmd.mktemp=yes\ncmd.tar=yes\ndisk_free_kb=1024\n");
+        let problems = report.problems(false);
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].contains("root"));
+
+        // Offline installs don't need curl
+        assert_eq!(report.problems(true).len(), 2);
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use error::Result;
+use std::{error, fmt};
+
+/// How a non-root user becomes root on the host.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BecomeMethod {
+    Sudo,
+    Doas,
+    Su,
+}
+
+impl Default for BecomeMethod {
+    fn default() -> BecomeMethod {
+        BecomeMethod::Sudo
+    }
+}
+
+impl BecomeMethod {
+    pub fn new(method: &str) -> Result<BecomeMethod> {
+        match method {
+            "sudo" => Ok(BecomeMethod::Sudo),
+            "doas" => Ok(BecomeMethod::Doas),
+            "su" => Ok(BecomeMethod::Su),
+            _ => Err(PrivilegeError::InvalidMethod(method.into()).into()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            BecomeMethod::Sudo => "sudo",
+            BecomeMethod::Doas => "doas",
+            BecomeMethod::Su => "su",
+        }
+    }
+
+    /// A command that succeeds only if we can become root without a
+    /// password. Without a terminal, su fails rather than prompting.
+    pub fn check_cmd(&self) -> Option<&'static str> {
+        match *self {
+            BecomeMethod::Sudo => Some("sudo -n true"),
+            BecomeMethod::Doas => Some("doas -n true"),
+            BecomeMethod::Su => Some("su root -c true < /dev/null"),
+        }
+    }
+}
+
+// su takes a single command string rather than argv, so this joins
+// its arguments into one, quoting each as `shell_quote` does.
+const SU_WRAPPER: &'static str = "_cmd=; for _arg; do _quoted=; while :; do case $_arg in *\\'*) _head=${_arg%%\\'*}; _quoted=\"$_quoted$_head'\\\\''\"; _arg=${_arg#*\\'} ;; *) break ;; esac; done; _cmd=\"$_cmd '$_quoted$_arg'\"; done; exec su root -c \"$_cmd\"";
+
+/// A working way to become root.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Escalation {
+    method: BecomeMethod,
+    password: Option<String>,
+}
+
+impl Escalation {
+    pub fn new(method: BecomeMethod, password: Option<&str>) -> Result<Escalation> {
+        match (method, password) {
+            // doas and most su implementations only read passwords
+            // from a terminal
+            (BecomeMethod::Doas, Some(_)) |
+            (BecomeMethod::Su, Some(_)) => Err(PrivilegeError::PasswordUnsupported(method.name()).into()),
+            _ => Ok(Escalation {
+                method: method,
+                password: password.map(|p| p.into()),
+            }),
+        }
+    }
+
+    /// Shell prefix that runs the following command as root, feeding
+    /// it the password on stdin if needed.
+    pub fn prefix(&self) -> String {
+        match (self.method, self.password.as_ref()) {
+            (BecomeMethod::Sudo, None) => "sudo".into(),
+            (BecomeMethod::Sudo, Some(p)) => format!("printf '%s\\n' {} | sudo -S -p ''", shell_quote(p)),
+            (BecomeMethod::Doas, _) => "doas".into(),
+            (BecomeMethod::Su, _) => format!("sh -c {} su", shell_quote(SU_WRAPPER)),
+        }
+    }
+}
+
+impl fmt::Display for Escalation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.password {
+            Some(_) => write!(f, "{} with password", self.method.name()),
+            None => write!(f, "passwordless {}", self.method.name()),
+        }
+    }
+}
+
+/// Quote `s` for safe use as a single shell word.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[derive(Debug)]
+pub enum PrivilegeError {
+    InvalidMethod(String),
+    PasswordUnsupported(&'static str),
+    Unavailable(&'static str),
+}
+
+impl fmt::Display for PrivilegeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PrivilegeError::InvalidMethod(ref m) => write!(f, "Unknown become method: {}", m),
+            PrivilegeError::PasswordUnsupported(m) => write!(f, "{} cannot be given a password non-interactively; configure it to not require one", m),
+            PrivilegeError::Unavailable(m) => write!(f, "Cannot become root with {} without a password; use --sudo-password or --sudo-password-env", m),
+        }
+    }
+}
+
+impl error::Error for PrivilegeError {
+    fn description(&self) -> &str {
+        match *self {
+            PrivilegeError::InvalidMethod(_) => "Unknown become method",
+            PrivilegeError::PasswordUnsupported(_) => "Become method cannot be given a password",
+            PrivilegeError::Unavailable(_) => "Cannot become root without a password",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    use super::*;
+    use tempdir::TempDir;
+    use transport::{LocalTransport, Transport};
+
+    #[test]
+    fn test_prefix() {
+        assert_eq!(Escalation::new(BecomeMethod::Sudo, None).unwrap().prefix(), "sudo");
+        assert_eq!(Escalation::new(BecomeMethod::Sudo, Some("pa'ss")).unwrap().prefix(), "printf '%s\\n' 'pa'\\''ss' | sudo -S -p ''");
+        assert_eq!(Escalation::new(BecomeMethod::Doas, None).unwrap().prefix(), "doas");
+        assert!(Escalation::new(BecomeMethod::Doas, Some("pass")).is_err());
+        assert!(Escalation::new(BecomeMethod::Su, None).unwrap().prefix().starts_with("sh -c '"));
+        assert!(Escalation::new(BecomeMethod::Su, Some("pass")).is_err());
+    }
+
+    #[test]
+    fn test_su_prefix_quoting() {
+        // Stand in for su, running the command it's given with sh
+        let dir = TempDir::new("test_su_prefix_quoting").unwrap();
+        let su = dir.path().join("su");
+        {
+            let mut fh = File::create(&su).unwrap();
+            fh.write_all(b"#!/bin/sh\n[ \"$1\" = root ] && [ \"$2\" = -c ] || exit 99\nexec /bin/sh -c \"$3\"\n").unwrap();
+        }
+        fs::set_permissions(&su, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let prefix = Escalation::new(BecomeMethod::Su, None).unwrap().prefix();
+        let cmd = format!("PATH={}:$PATH {} sh -c 'printf \"[%s]\\n\" \"$@\"' sh 'a b' \"it's\" '$HOME'", dir.path().display(), prefix);
+        let mut transport = LocalTransport::new();
+        assert_eq!(transport.exec(&cmd).unwrap(), "[a b]\n[it's]\n[$HOME]\n");
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+}
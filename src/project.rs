@@ -10,6 +10,7 @@ use czmq::{ZCert, ZFrame, ZMsg, ZSock, SocketType};
 use error::Result;
 use inapi::ProjectConfig;
 use language::{Language, LanguageProject, CProject, PhpProject, RustProject};
+use serde_json::Value;
 use {read_conf, write_conf};
 use std::{error, fmt, fs};
 use std::io::Write;
@@ -174,6 +175,15 @@ If you do not have a user certificate, obtain one from your administrator.", pat
     }
 }
 
+/// Read a key from project.json that isn't part of `ProjectConfig`.
+pub fn read_conf_key<P: AsRef<Path>>(project_path: P, key: &str) -> Result<Option<Value>> {
+    let mut buf = project_path.as_ref().to_owned();
+    buf.push(CONFIGNAME);
+
+    let mut conf: Value = read_conf(&buf)?;
+    Ok(conf.as_object_mut().and_then(|o| o.remove(key)))
+}
+
 #[derive(Debug)]
 pub enum ProjectError {
     CreateFailed(String),
@@ -233,6 +243,26 @@ mod tests {
         assert!(Project::load(&path).is_ok());
     }
 
+    #[test]
+    fn test_read_conf_key() {
+        let dir = TempDir::new("test_read_conf_key").unwrap();
+        let mut path = dir.path().to_owned();
+
+        path.push(CONFIGNAME);
+        let mut file = File::create(&path).unwrap();
+        file.write_all("{
+            \"language\":\"Php\",
+            \"auth_server\":\"auth.example.com\",
+            \"auth_api_port\": 7101,
+            \"auth_update_port\": 7102,
+            \"extra\": \"yak\"
+        }".as_bytes()).unwrap();
+        path.pop();
+
+        assert_eq!(read_conf_key(&path, "extra").unwrap().unwrap().as_str(), Some("yak"));
+        assert!(read_conf_key(&path, "missing").unwrap().is_none());
+    }
+
     #[test]
     fn test_create_exists() {
         let dir = TempDir::new("test_create_exists").unwrap();
@@ -0,0 +1,140 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use error::Result;
+use std::{env, error, fmt, fs};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::process::{Command, Stdio};
+
+const TTY: &'static str = "/dev/tty";
+
+/// Whether we have a terminal to prompt on. We use the controlling
+/// terminal rather than stdin, as stdin may be an inventory.
+pub fn is_interactive() -> bool {
+    fs::OpenOptions::new().read(true).write(true).open(TTY).is_ok()
+}
+
+/// Prompt for a secret on the terminal without echoing it.
+pub fn prompt(msg: &str) -> Result<String> {
+    let mut tty = fs::OpenOptions::new().read(true).write(true).open(TTY)?;
+    tty.write_all(msg.as_bytes())?;
+    tty.flush()?;
+
+    set_echo(false)?;
+    let mut line = String::new();
+    let result = BufReader::new(&tty).read_line(&mut line);
+    set_echo(true)?;
+    tty.write_all(b"\n")?;
+    result?;
+
+    Ok(trim_newline(&line).into())
+}
+
+/// Ask a question on the terminal, echoing the answer.
+pub fn ask(msg: &str) -> Result<String> {
+    let mut tty = fs::OpenOptions::new().read(true).write(true).open(TTY)?;
+    tty.write_all(msg.as_bytes())?;
+    tty.flush()?;
+
+    let mut line = String::new();
+    BufReader::new(&tty).read_line(&mut line)?;
+    Ok(trim_newline(&line).into())
+}
+
+/// Read a secret from an environment variable.
+pub fn from_env(var: &str) -> Result<String> {
+    env::var(var).or(Err(SecretError::EnvNotSet(var.into()).into()))
+}
+
+/// Read a secret from the first line of an inherited file descriptor,
+/// e.g. `incli ... --pass-fd 3 3< secret.txt`.
+pub fn from_fd(fd: i32) -> Result<String> {
+    let mut fh = unsafe { fs::File::from_raw_fd(fd) };
+    let mut contents = String::new();
+    let result = fh.read_to_string(&mut contents);
+    // The fd still belongs to our caller, so don't let `fh` close it
+    fh.into_raw_fd();
+    result?;
+
+    match contents.lines().next() {
+        Some(line) => Ok(trim_newline(line).into()),
+        None => Err(SecretError::Empty(fd).into()),
+    }
+}
+
+fn set_echo(on: bool) -> Result<()> {
+    let tty = fs::File::open(TTY)?;
+    Command::new("stty")
+            .arg(if on { "echo" } else { "-echo" })
+            .stdin(Stdio::from(tty))
+            .status()?;
+    Ok(())
+}
+
+fn trim_newline(s: &str) -> &str {
+    s.trim_right_matches(|c| c == '\r' || c == '\n')
+}
+
+#[derive(Debug)]
+pub enum SecretError {
+    Empty(i32),
+    EnvNotSet(String),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SecretError::Empty(fd) => write!(f, "No secret could be read from file descriptor {}", fd),
+            SecretError::EnvNotSet(ref v) => write!(f, "Environment variable {} is not set", v),
+        }
+    }
+}
+
+impl error::Error for SecretError {
+    fn description(&self) -> &str {
+        match *self {
+            SecretError::Empty(_) => "No secret could be read from file descriptor",
+            SecretError::EnvNotSet(_) => "Environment variable is not set",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_from_env() {
+        env::set_var("INCLI_TEST_SECRET", "s3cr3t");
+        assert_eq!(from_env("INCLI_TEST_SECRET").unwrap(), "s3cr3t");
+        assert!(from_env("INCLI_TEST_SECRET_MISSING").is_err());
+    }
+
+    #[test]
+    fn test_from_fd() {
+        let dir = TempDir::new("test_from_fd").unwrap();
+        let path = dir.path().join("secret.txt");
+        let mut fh = fs::File::create(&path).unwrap();
+        fh.write_all(b"s3cr3t\nignored\n").unwrap();
+
+        let fh = fs::File::open(&path).unwrap();
+        assert_eq!(from_fd(fh.as_raw_fd()).unwrap(), "s3cr3t");
+        // The fd is still open, but at EOF
+        assert!(from_fd(fh.as_raw_fd()).is_err());
+    }
+
+    #[test]
+    fn test_trim_newline() {
+        assert_eq!(super::trim_newline("pass word\r\n"), "pass word");
+    }
+}
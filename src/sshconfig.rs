@@ -0,0 +1,246 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Support for the subset of OpenSSH's client config that affects how
+//! we connect: HostName, User, Port, IdentityFile and ProxyJump.
+//! `Match` and `Include` aren't supported, and their blocks are
+//! skipped.
+
+use error::Result;
+use ssh::{JumpHost, SshError};
+use std::{env, fs};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+/// Settings from ~/.ssh/config that apply to a host.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HostConfig {
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_file: Option<String>,
+    /// Jump hosts as "[user@]host[:port]", comma separated.
+    pub proxy_jump: Option<String>,
+}
+
+impl HostConfig {
+    /// Look up `host` in the user's ~/.ssh/config, if there is one.
+    pub fn load(host: &str) -> Result<HostConfig> {
+        match config_path() {
+            Some(ref path) if path.exists() => {
+                let fh = fs::File::open(path)?;
+                HostConfig::parse(BufReader::new(fh), host)
+            },
+            _ => Ok(HostConfig::default()),
+        }
+    }
+
+    /// Gather the settings for `host`. As with OpenSSH, the first value
+    /// found for each setting wins.
+    pub fn parse<R: BufRead>(reader: R, host: &str) -> Result<HostConfig> {
+        let mut conf = HostConfig::default();
+        // Settings before the first Host line apply to every host
+        let mut matched = true;
+
+        for line in reader.lines() {
+            let line = line?;
+            let (key, value) = match split_line(&line) {
+                Some(kv) => kv,
+                None => continue,
+            };
+
+            match &*key {
+                "host" => matched = host_matches(&value, host),
+                "match" => matched = false,
+                _ if !matched => (),
+                "hostname" => set(&mut conf.hostname, value.replace("%h", host)),
+                "user" => set(&mut conf.user, value),
+                "port" => if conf.port.is_none() {
+                    conf.port = Some(value.parse().or(Err(SshError::InvalidConfig(line.trim().into())))?);
+                },
+                "identityfile" => set(&mut conf.identity_file, expand_home(&value)),
+                "proxyjump" => set(&mut conf.proxy_jump, value),
+                _ => (),
+            }
+        }
+
+        // "none" disables ProxyJump for hosts matched by a later wildcard
+        if conf.proxy_jump.as_ref().map(|p| p == "none").unwrap_or(false) {
+            conf.proxy_jump = None;
+        }
+
+        Ok(conf)
+    }
+
+    /// Fill in any settings for a jump host that weren't given
+    /// explicitly.
+    pub fn apply_to_jump(&self, jump: &mut JumpHost) {
+        if let Some(ref h) = self.hostname {
+            jump.host = h.clone();
+        }
+        if jump.username.is_none() {
+            jump.username = self.user.clone();
+        }
+        // JumpHost::parse can't tell us whether 22 was explicit
+        if jump.port == 22 {
+            jump.port = self.port.unwrap_or(22);
+        }
+        // As for the host itself, a password isn't a key passphrase
+        if jump.identity_file.is_none() && jump.password.is_none() {
+            jump.identity_file = self.identity_file.clone();
+        }
+    }
+}
+
+fn set(field: &mut Option<String>, value: String) {
+    if field.is_none() {
+        *field = Some(value);
+    }
+}
+
+/// Split a config line into a lowercase keyword and its value, which
+/// may be separated by whitespace or '='.
+fn split_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let i = line.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(line.len());
+    let key = line[..i].to_lowercase();
+    let value = line[i..].trim_left_matches(|c: char| c.is_whitespace() || c == '=')
+                         .trim_right()
+                         .trim_matches('"');
+    Some((key, value.into()))
+}
+
+/// Whether `host` matches a Host line's patterns. A match on any
+/// negated pattern excludes the host.
+fn host_matches(patterns: &str, host: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns.split_whitespace() {
+        if pattern.starts_with('!') {
+            if glob_match(&pattern[1..], host) {
+                return false;
+            }
+        } else if glob_match(pattern, host) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// Match `text` against a pattern containing '*' and '?' wildcards.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            // Let the last '*' swallow one more character
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+fn expand_home(path: &str) -> String {
+    match env::var("HOME") {
+        Ok(ref home) if path.starts_with("~/") => format!("{}{}", home, &path[1..]),
+        _ => path.into(),
+    }
+}
+
+fn config_path() -> Option<PathBuf> {
+    env::var("HOME").ok().map(|home| {
+        let mut buf = PathBuf::from(home);
+        buf.push(".ssh/config");
+        buf
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh::JumpHost;
+    use std::io::Cursor;
+    use super::*;
+
+    const CONFIG: &'static str = "# Global settings
+User deploy
+
+Host web? !web9
+    HostName %h.internal.example.com
+    Port=2222
+    IdentityFile \"/keys/web\"
+    ProxyJump ops@bastion
+
+Match host db1
+    User nobody
+
+Host bastion
+    HostName bastion.example.com
+    Port 2200
+
+Host *
+    User ignored
+    ProxyJump none
+";
+
+    #[test]
+    fn test_parse() {
+        let conf = HostConfig::parse(Cursor::new(CONFIG), "web1").unwrap();
+        assert_eq!(conf, HostConfig {
+            hostname: Some("web1.internal.example.com".into()),
+            user: Some("deploy".into()),
+            port: Some(2222),
+            identity_file: Some("/keys/web".into()),
+            proxy_jump: Some("ops@bastion".into()),
+        });
+
+        let conf = HostConfig::parse(Cursor::new(CONFIG), "web9").unwrap();
+        assert!(conf.hostname.is_none());
+        assert!(conf.proxy_jump.is_none());
+
+        let conf = HostConfig::parse(Cursor::new(CONFIG), "db1").unwrap();
+        assert_eq!(conf.user.unwrap(), "deploy");
+
+        assert!(HostConfig::parse(Cursor::new("Port ssh"), "web1").is_err());
+    }
+
+    #[test]
+    fn test_apply_to_jump() {
+        let conf = HostConfig::parse(Cursor::new(CONFIG), "bastion").unwrap();
+        let mut jump = JumpHost::parse("ops@bastion").unwrap();
+        conf.apply_to_jump(&mut jump);
+        assert_eq!(jump.host, "bastion.example.com");
+        assert_eq!(jump.port, 2200);
+        assert_eq!(jump.username.unwrap(), "ops");
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(super::glob_match("*.example.com", "web1.example.com"));
+        assert!(super::glob_match("web?", "web1"));
+        assert!(!super::glob_match("web?", "web10"));
+        assert!(super::glob_match("*", "anything"));
+        assert!(!super::glob_match("db*", "web1"));
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use error::Result;
+use project;
+use serde_json::Value;
+use std::{error, fmt, fs};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+/// Projects can override the built-in bootstrap script by adding this
+/// file to the project root, or by setting "bootstrap_template" in
+/// project.json.
+pub const TEMPLATE_NAME: &'static str = "bootstrap.sh.tpl";
+
+/// Variables available to every template as `{{NAME}}`. Extra
+/// variables passed with `--var key=value` are available as `{{key}}`.
+pub const BUILTIN_VARS: &'static [(&'static str, &'static str)] = &[
+    ("AGENTCERT", "The host's certificate, including its secret key"),
+    ("AUTHCERT", "The auth server's public certificate"),
+    ("AUTHHOST", "Hostname of the auth server"),
+    ("AUTHPORT", "Auth server update port"),
+    ("HOSTNAME", "Name of the host being bootstrapped"),
+    ("INSTALLAGENT", "Commands that fetch and install the agent"),
+    ("POSTINSTALL", "Script passed with -n or --postinstall-file"),
+    ("PREINSTALL", "Script passed with -m or --preinstall-file"),
+    ("SUDO", "Command prefix that runs a command as root, or empty if connecting as root"),
+];
+
+pub struct Template {
+    source: String,
+}
+
+impl Template {
+    pub fn new(source: &str) -> Template {
+        Template {
+            source: source.into(),
+        }
+    }
+
+    /// Load the project's bootstrap template, falling back to
+    /// `default` if the project doesn't have one.
+    pub fn load<P: AsRef<Path>>(project_path: P, default: &str) -> Result<Template> {
+        let mut path = project_path.as_ref().to_owned();
+
+        match project::read_conf_key(&project_path, "bootstrap_template")? {
+            Some(Value::String(p)) => path.push(p),
+            Some(v) => return Err(TemplateError::InvalidPath(v.to_string()).into()),
+            None => {
+                path.push(TEMPLATE_NAME);
+                if !path.exists() {
+                    return Ok(Template::new(default));
+                }
+            }
+        }
+
+        let mut fh = fs::File::open(&path)?;
+        let mut source = String::new();
+        fh.read_to_string(&mut source)?;
+        Ok(Template::new(&source))
+    }
+
+    /// Replace each `{{NAME}}` with its value from `vars`. Values are
+    /// inserted verbatim, so placeholders inside them aren't expanded.
+    pub fn render(&self, vars: &HashMap<String, String>) -> Result<String> {
+        let mut out = String::with_capacity(self.source.len());
+        let mut unknown = Vec::new();
+        let mut rest = &self.source[..];
+
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let name = match rest[2..].find("}}") {
+                Some(end) if is_name(&rest[2..end + 2]) => &rest[2..end + 2],
+                _ => {
+                    // Not a placeholder, so leave it be
+                    out.push_str("{{");
+                    rest = &rest[2..];
+                    continue;
+                }
+            };
+
+            match vars.get(name) {
+                Some(value) => out.push_str(value),
+                None => {
+                    if !unknown.iter().any(|u| u == name) {
+                        unknown.push(name.to_owned());
+                    }
+                }
+            }
+            rest = &rest[name.len() + 4..];
+        }
+        out.push_str(rest);
+
+        if unknown.is_empty() {
+            Ok(out)
+        } else {
+            Err(TemplateError::UnknownPlaceholders(unknown).into())
+        }
+    }
+}
+
+/// A listing of the built-in variables and what they hold.
+pub fn builtin_vars_help() -> String {
+    let width = BUILTIN_VARS.iter().map(|&(name, _)| name.len()).max().unwrap_or(0) + 4;
+    let lines: Vec<String> = BUILTIN_VARS.iter()
+                                         .map(|&(name, desc)| format!("  {:<w$}  {}", format!("{{{{{}}}}}", name), desc, w = width))
+                                         .collect();
+    lines.join("\n")
+}
+
+/// Parse a `--var key=value` argument.
+pub fn parse_var(var: &str) -> Result<(String, String)> {
+    let mut parts = var.splitn(2, '=');
+    let (key, value) = match (parts.next(), parts.next()) {
+        (Some(k), Some(v)) if is_name(k) => (k, v),
+        _ => return Err(TemplateError::InvalidVar(var.into()).into()),
+    };
+
+    if BUILTIN_VARS.iter().any(|&(name, _)| name == key) {
+        Err(TemplateError::ReservedVar(key.into()).into())
+    } else {
+        Ok((key.into(), value.into()))
+    }
+}
+
+fn is_name(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    InvalidPath(String),
+    InvalidVar(String),
+    ReservedVar(String),
+    UnknownPlaceholders(Vec<String>),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TemplateError::InvalidPath(ref p) => write!(f, "\"bootstrap_template\" must be a path, got {}", p),
+            TemplateError::InvalidVar(ref v) => write!(f, "Invalid template variable \"{}\", expected key=value", v),
+            TemplateError::ReservedVar(ref k) => write!(f, "Template variable {} is built in and cannot be overridden", k),
+            TemplateError::UnknownPlaceholders(ref p) => write!(f, "Template contains unknown placeholders: {}. Run `incli host bootstrap --list-vars` to see the built-in variables.", p.join(", ")),
+        }
+    }
+}
+
+impl error::Error for TemplateError {
+    fn description(&self) -> &str {
+        match *self {
+            TemplateError::InvalidPath(_) => "Invalid bootstrap template path",
+            TemplateError::InvalidVar(_) => "Invalid template variable",
+            TemplateError::ReservedVar(_) => "Template variable is built in",
+            TemplateError::UnknownPlaceholders(_) => "Template contains unknown placeholders",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use project;
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::Write;
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_load() {
+        let dir = TempDir::new("test_template_load").unwrap();
+        let mut path = dir.path().to_owned();
+
+        path.push(project::CONFIGNAME);
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"{\"language\":\"Php\"}").unwrap();
+        path.pop();
+
+        assert_eq!(Template::load(&path, "default").unwrap().source, "default");
+
+        path.push(TEMPLATE_NAME);
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"custom").unwrap();
+        path.pop();
+
+        assert_eq!(Template::load(&path, "default").unwrap().source, "custom");
+    }
+
+    #[test]
+    fn test_builtin_vars_help() {
+        let help = builtin_vars_help();
+        assert_eq!(help.lines().count(), BUILTIN_VARS.len());
+        assert!(help.starts_with("  {{AGENTCERT}}     The host's certificate"));
+    }
+
+    #[test]
+    fn test_render() {
+        let mut vars = HashMap::new();
+        vars.insert("HOSTNAME".to_owned(), "web1".to_owned());
+        vars.insert("role".to_owned(), "{{HOSTNAME}}".to_owned());
+
+        let template = Template::new("echo {{HOSTNAME}} {{role}} {{ not a var }} {{");
+        assert_eq!(template.render(&vars).unwrap(), "echo web1 {{HOSTNAME}} {{ not a var }} {{");
+
+        let template = Template::new("{{HOSTNAM}} {{SUDOO}} {{HOSTNAM}}");
+        match template.render(&vars) {
+            Err(::error::Error::Template(TemplateError::UnknownPlaceholders(p))) => assert_eq!(p, vec!["HOSTNAM", "SUDOO"]),
+            _ => panic!("Expected unknown placeholders"),
+        }
+    }
+
+    #[test]
+    fn test_parse_var() {
+        assert_eq!(parse_var("role=web=1").unwrap(), ("role".into(), "web=1".into()));
+        assert!(parse_var("role").is_err());
+        assert!(parse_var("=web").is_err());
+        assert!(parse_var("SUDO=doas").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use error::{Error, Result};
+use privilege::shell_quote;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use super::{process_exec, process_stream, Transport};
+
+/// Runs commands inside a running container using `docker exec`.
+pub struct DockerTransport {
+    container: String,
+}
+
+impl DockerTransport {
+    pub fn new(container: &str) -> DockerTransport {
+        DockerTransport {
+            container: container.into(),
+        }
+    }
+
+    fn command(&self, cmd: &str) -> Command {
+        let mut command = Command::new("docker");
+        command.arg("exec").arg(&self.container).arg("/bin/sh").arg("-c").arg(cmd);
+        command
+    }
+}
+
+impl Transport for DockerTransport {
+    fn exec(&mut self, cmd: &str) -> Result<String> {
+        process_exec(&mut self.command(cmd))
+    }
+
+    fn exec_stream(&mut self, cmd: &str, stdout: &mut Write, stderr: &mut Write) -> Result<i32> {
+        process_stream(&mut self.command(cmd), stdout, stderr)
+    }
+
+    fn upload(&mut self, local_path: &Path, remote_path: &str, mode: i32) -> Result<()> {
+        process_exec(Command::new("docker")
+                            .arg("cp")
+                            .arg(local_path)
+                            .arg(format!("{}:{}", self.container, remote_path)))?;
+        self.exec(&format!("chmod {:o} {}", mode, shell_quote(remote_path)))?;
+        Ok(())
+    }
+
+    fn write_file(&mut self, contents: &[u8], remote_path: &str, mode: i32) -> Result<()> {
+        // Pipe the contents in, so they never touch the local disk.
+        // noclobber stops us writing through an existing file.
+        let cmd = format!("set -C && umask 077 && cat > {0} && chmod {1:o} {0}", shell_quote(remote_path), mode);
+        let mut child = Command::new("docker")
+                                .arg("exec").arg("-i").arg(&self.container)
+                                .arg("/bin/sh").arg("-c").arg(cmd)
+                                .stdin(Stdio::piped())
+                                .stdout(Stdio::null())
+                                .stderr(Stdio::piped())
+                                .spawn()?;
+        child.stdin.take().unwrap().write_all(contents)?;
+
+        let output = child.wait_with_output()?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(Error::Bootstrap(format!("Could not write {}: {}", remote_path, String::from_utf8_lossy(&output.stderr))))
+        }
+    }
+}
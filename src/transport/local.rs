@@ -0,0 +1,104 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use error::Result;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::process::Command;
+use super::{process_exec, process_stream, Transport};
+
+/// Runs commands on the machine incli is running on.
+#[derive(Default)]
+pub struct LocalTransport;
+
+impl LocalTransport {
+    pub fn new() -> LocalTransport {
+        LocalTransport
+    }
+}
+
+impl Transport for LocalTransport {
+    fn exec(&mut self, cmd: &str) -> Result<String> {
+        process_exec(Command::new("/bin/sh").arg("-c").arg(cmd))
+    }
+
+    fn exec_stream(&mut self, cmd: &str, stdout: &mut Write, stderr: &mut Write) -> Result<i32> {
+        process_stream(Command::new("/bin/sh").arg("-c").arg(cmd), stdout, stderr)
+    }
+
+    fn upload(&mut self, local_path: &Path, remote_path: &str, mode: i32) -> Result<()> {
+        fs::copy(local_path, remote_path)?;
+        fs::set_permissions(remote_path, fs::Permissions::from_mode(mode as u32))?;
+        Ok(())
+    }
+
+    fn write_file(&mut self, contents: &[u8], remote_path: &str, mode: i32) -> Result<()> {
+        let mut fh = fs::OpenOptions::new().write(true).create_new(true).mode(mode as u32).open(remote_path)?;
+        fh.write_all(contents)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    use super::*;
+    use tempdir::TempDir;
+    use transport::Transport;
+
+    #[test]
+    fn test_exec() {
+        let mut transport = LocalTransport::new();
+        assert_eq!(transport.exec("echo hello").unwrap(), "hello\n");
+        assert!(transport.exec("echo nope >&2; exit 1").is_err());
+    }
+
+    #[test]
+    fn test_exec_stream() {
+        let mut transport = LocalTransport::new();
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let status = transport.exec_stream("echo out; echo err >&2; exit 3", &mut stdout, &mut stderr).unwrap();
+        assert_eq!(status, 3);
+        assert_eq!(stdout, b"out\n");
+        assert_eq!(stderr, b"err\n");
+    }
+
+    #[test]
+    fn test_upload() {
+        let dir = TempDir::new("test_upload").unwrap();
+        let local = dir.path().join("local");
+        let remote = dir.path().join("remote");
+
+        let mut fh = fs::File::create(&local).unwrap();
+        fh.write_all(b"payload").unwrap();
+
+        let mut transport = LocalTransport::new();
+        transport.upload(&local, remote.to_str().unwrap(), 0o600).unwrap();
+
+        let meta = fs::metadata(&remote).unwrap();
+        assert_eq!(meta.len(), 7);
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_write_file() {
+        let dir = TempDir::new("test_write_file").unwrap();
+        let remote = dir.path().join("script.sh");
+        let remote = remote.to_str().unwrap();
+
+        let mut transport = LocalTransport::new();
+        transport.write_file(b"exit 0\n", remote, 0o700).unwrap();
+        assert_eq!(fs::metadata(remote).unwrap().permissions().mode() & 0o777, 0o700);
+        assert!(transport.write_file(b"exit 1\n", remote, 0o700).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+mod docker;
+mod local;
+mod ssh;
+
+pub use self::docker::DockerTransport;
+pub use self::local::LocalTransport;
+pub use self::ssh::SshTransport;
+
+use error::{Error, Result};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+/// A way of running commands on the host being bootstrapped.
+pub trait Transport {
+    /// Run `cmd` in a shell, returning its stdout. Fails if the
+    /// command exits non-zero.
+    fn exec(&mut self, cmd: &str) -> Result<String>;
+    /// Run `cmd` in a shell, writing its stdout and stderr as they
+    /// arrive. Returns the command's exit status.
+    fn exec_stream(&mut self, cmd: &str, stdout: &mut Write, stderr: &mut Write) -> Result<i32>;
+    /// Copy a local file to `remote_path` with permissions `mode`.
+    fn upload(&mut self, local_path: &Path, remote_path: &str, mode: i32) -> Result<()>;
+    /// Create `remote_path` with `contents` and permissions `mode`.
+    /// Fails if the file already exists.
+    fn write_file(&mut self, contents: &[u8], remote_path: &str, mode: i32) -> Result<()>;
+}
+
+/// Run a local process to completion, returning its stdout.
+fn process_exec(cmd: &mut Command) -> Result<String> {
+    let output = cmd.output()?;
+    let out = String::from_utf8(output.stdout)?;
+
+    if output.status.success() {
+        Ok(out)
+    } else {
+        let err = String::from_utf8_lossy(&output.stderr);
+        Err(Error::Bootstrap(format!("stdout: {}\nstderr: {}", out, err)))
+    }
+}
+
+/// Run a local process, forwarding its output as it arrives.
+fn process_stream(cmd: &mut Command, stdout: &mut Write, stderr: &mut Write) -> Result<i32> {
+    let mut child = cmd.stdin(Stdio::null())
+                       .stdout(Stdio::piped())
+                       .stderr(Stdio::piped())
+                       .spawn()?;
+
+    // Each pipe gets its own reader thread so that neither can fill up
+    // and stall the child. Chunks are tagged with whether they came
+    // from stderr.
+    let (tx, rx) = mpsc::channel();
+    let readers = vec![
+        pipe_reader(child.stdout.take().unwrap(), false, tx.clone()),
+        pipe_reader(child.stderr.take().unwrap(), true, tx),
+    ];
+
+    for (is_stderr, chunk) in rx.iter() {
+        if is_stderr {
+            stderr.write_all(&chunk)?;
+        } else {
+            stdout.write_all(&chunk)?;
+        }
+    }
+
+    for reader in readers {
+        let _ = reader.join();
+    }
+
+    // A process killed by a signal has no exit code
+    Ok(child.wait()?.code().unwrap_or(-1))
+}
+
+fn pipe_reader<R: Read + Send + 'static>(mut pipe: R, is_stderr: bool, tx: mpsc::Sender<(bool, Vec<u8>)>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        loop {
+            match pipe.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => if tx.send((is_stderr, buf[..n].to_vec())).is_err() {
+                    break;
+                },
+            }
+        }
+    })
+}
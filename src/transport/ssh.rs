@@ -0,0 +1,175 @@
+// Copyright 2015-2017 Intecture Developers. See the COPYRIGHT file at the
+// top-level directory of this distribution and at
+// https://intecture.io/COPYRIGHT.
+//
+// Licensed under the Mozilla Public License 2.0 <LICENSE or
+// https://www.tldrlegal.com/l/mpl-2.0>. This file may not be copied,
+// modified, or distributed except according to those terms.
+
+use error::{Error, Result};
+use hostkey::HostKeyCheck;
+use ssh::{self, Credentials, JumpHost, SshError, TimeoutStage, Timeouts};
+use ssh2::{Channel, OpenFlags, OpenType, Session};
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+use super::Transport;
+
+/// Runs commands over an authenticated SSH session.
+pub struct SshTransport {
+    session: Session,
+    hostname: String,
+    command_timeout: Option<Duration>,
+}
+
+impl SshTransport {
+    /// Connect and authenticate to `hostname`, retrying with
+    /// exponential backoff if the connection fails in a way that may be
+    /// temporary.
+    pub fn connect(hostname: &str,
+                   port: u16,
+                   credentials: &Credentials,
+                   host_key_check: &HostKeyCheck,
+                   jump_hosts: &[JumpHost],
+                   timeouts: &Timeouts) -> Result<SshTransport> {
+        let mut attempt = 0;
+        let sess = loop {
+            match ssh::connect(hostname, port, jump_hosts, host_key_check, credentials.interactive, timeouts) {
+                Ok(conn) => break conn,
+                Err(ref e) if attempt < timeouts.retries && ssh::is_transient(e) => {
+                    let delay = 1 << attempt;
+                    println!("{}; retrying in {}s", e, delay);
+                    thread::sleep(Duration::from_secs(delay));
+                    attempt += 1;
+                },
+                Err(e) => return Err(e),
+            }
+        };
+        ssh::authenticate(&sess, hostname, credentials)?;
+        sess.set_timeout(timeouts.command.map(ssh::millis).unwrap_or(0));
+
+        Ok(SshTransport {
+            session: sess,
+            hostname: hostname.into(),
+            command_timeout: timeouts.command,
+        })
+    }
+
+    fn timeout_error(&self) -> Error {
+        SshError::Timeout(self.hostname.clone(), TimeoutStage::Command, self.command_timeout.map(|t| t.as_secs()).unwrap_or(0)).into()
+    }
+
+    fn exec_inner(&mut self, cmd: &str) -> Result<String> {
+        let mut channel = self.session.channel_session()?;
+        channel.exec(cmd)?;
+        channel.send_eof()?;
+        channel.wait_eof()?;
+
+        let mut out = String::new();
+        channel.read_to_string(&mut out)?;
+
+        if channel.exit_status()? == 0 {
+            Ok(out)
+        } else {
+            let mut stderr = channel.stderr();
+            let mut err = String::new();
+            stderr.read_to_string(&mut err)?;
+            Err(Error::Bootstrap(format!("stdout: {}\nstderr: {}", out, err)))
+        }
+    }
+}
+
+impl Transport for SshTransport {
+    fn exec(&mut self, cmd: &str) -> Result<String> {
+        match self.exec_inner(cmd) {
+            Err(ref e) if ssh::is_timeout(e) => Err(self.timeout_error()),
+            result => result,
+        }
+    }
+
+    fn exec_stream(&mut self, cmd: &str, stdout: &mut Write, stderr: &mut Write) -> Result<i32> {
+        let mut channel = self.session.channel_session()?;
+        channel.exec(cmd)?;
+        channel.send_eof()?;
+
+        // Switch to non-blocking so that a quiet stdout doesn't hold
+        // up stderr and vice versa. The session's timeout doesn't apply
+        // in non-blocking mode, so we keep our own deadline.
+        let deadline = self.command_timeout.map(|t| Instant::now() + t);
+        self.session.set_blocking(false);
+        let result = stream(&mut channel, stdout, stderr, deadline);
+        self.session.set_blocking(true);
+        match result {
+            Ok(true) => (),
+            Ok(false) => return Err(self.timeout_error()),
+            Err(e) => return Err(e),
+        }
+
+        channel.wait_close()?;
+        Ok(channel.exit_status()?)
+    }
+
+    fn upload(&mut self, local_path: &Path, remote_path: &str, mode: i32) -> Result<()> {
+        let mut fh = File::open(local_path)?;
+        let size = fh.metadata()?.len();
+
+        let mut channel = self.session.scp_send(Path::new(remote_path), mode, size, None)?;
+        io::copy(&mut fh, &mut channel)?;
+        channel.send_eof()?;
+        channel.wait_eof()?;
+        channel.close()?;
+        channel.wait_close()?;
+
+        Ok(())
+    }
+
+    fn write_file(&mut self, contents: &[u8], remote_path: &str, mode: i32) -> Result<()> {
+        let sftp = self.session.sftp()?;
+        let mut fh = sftp.open_mode(Path::new(remote_path), OpenFlags::WRITE | OpenFlags::EXCLUSIVE, mode, OpenType::File)?;
+        fh.write_all(contents)?;
+        Ok(())
+    }
+}
+
+/// Copy the channel's output until EOF, returning false if `deadline`
+/// passes first.
+fn stream(channel: &mut Channel, stdout: &mut Write, stderr: &mut Write, deadline: Option<Instant>) -> Result<bool> {
+    let mut buf = [0; 4096];
+
+    while !channel.eof() {
+        if deadline.map(|d| Instant::now() > d).unwrap_or(false) {
+            return Ok(false);
+        }
+
+        let mut idle = true;
+
+        match channel.read(&mut buf) {
+            Ok(0) => (),
+            Ok(n) => {
+                stdout.write_all(&buf[..n])?;
+                idle = false;
+            },
+            Err(ref e) if ssh::would_block(e) => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        match channel.stderr().read(&mut buf) {
+            Ok(0) => (),
+            Ok(n) => {
+                stderr.write_all(&buf[..n])?;
+                idle = false;
+            },
+            Err(ref e) if ssh::would_block(e) => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        if idle {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    Ok(true)
+}